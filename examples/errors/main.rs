@@ -2,7 +2,6 @@ use std::fmt::Display;
 use std::fs;
 
 use serde::export::Formatter;
-use thiserror;
 
 use anyhow::{anyhow, Context, Error};
 
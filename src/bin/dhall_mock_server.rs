@@ -1,25 +1,28 @@
 extern crate dhall_mock;
 
 use std::fs;
-use std::sync::{Arc, RwLock};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
 
-use anyhow::{Context, Error};
-use futures::future::join_all;
-use log::{info, warn};
+use anyhow::{anyhow, Context, Error};
+use log::info;
 use structopt::StructOpt;
 
-use dhall_mock::mock::service::{
-    add_expectations_in_state, load_dhall_expectation, SharedState, State,
-};
+use dhall_mock::mock::files::{configuration_files, load_configuration_files};
+use dhall_mock::mock::journal::Journal;
+use dhall_mock::mock::model::ProxyTarget;
+use dhall_mock::mock::scenario::Scenarios;
+use dhall_mock::mock::service::{collect_expired_expectations, State, EXPIRED_COLLECTION_INTERVAL};
+use dhall_mock::mock::watch::{ConfigurationWatch, DEFAULT_WATCH_INTERVAL};
 use dhall_mock::web::admin::AdminServerContext;
 use dhall_mock::web::mock::MockServerContext;
+use dhall_mock::web::tls::{ClientAuth, TlsContext};
 use dhall_mock::{start_logger, start_servers};
-use futures::TryFutureExt;
 
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(name = "dhall-mock")]
 struct CliOpt {
-    /// Dhall configuration files to parse
+    /// Dhall configuration files to parse, directories and glob patterns (ex: `mocks/**/*.dhall`) are expanded
     configuration_files: Vec<String>,
     /// http binding for server
     #[structopt(short, long, default_value = "0.0.0.0:8088")]
@@ -30,6 +33,33 @@ struct CliOpt {
     /// wait to compile all configuration files before starting web servers
     #[structopt(short, long)]
     wait: bool,
+    /// explain in 404 responses why the closest expectations refused the request
+    #[structopt(short, long)]
+    diagnostics: bool,
+    /// reload a configuration file when it or one of its local imports changes
+    #[structopt(long)]
+    watch: bool,
+    /// PEM certificate chain to serve the mock server over https
+    #[structopt(long, requires = "tls-key")]
+    tls_cert: Option<String>,
+    /// PEM private key (PKCS8 or RSA) of the https certificate
+    #[structopt(long, requires = "tls-cert")]
+    tls_key: Option<String>,
+    /// serve the mock server over https with a certificate generated at startup, available on the admin server
+    #[structopt(long, conflicts_with = "tls-cert")]
+    tls_self_signed: bool,
+    /// PEM CA bundle used to verify client certificates (mutual tls)
+    #[structopt(long)]
+    tls_client_ca: Option<String>,
+    /// client certificates requirement with --tls-client-ca : optional or required
+    #[structopt(long, default_value = "required")]
+    tls_client_auth: ClientAuth,
+    /// number of received requests kept in the journal
+    #[structopt(long, default_value = "1000")]
+    journal_capacity: usize,
+    /// forward the requests without matching expectation to this upstream url (ex: `http://localhost:8080`)
+    #[structopt(long)]
+    proxy_to: Option<ProxyTarget>,
 }
 
 #[tokio::main]
@@ -39,16 +69,36 @@ async fn main() -> Result<(), Error> {
     let cli_args = CliOpt::from_args();
 
     info!("Start dhall mock project 👋");
+    let configuration_files = configuration_files(&cli_args.configuration_files)?;
     let state = Arc::new(RwLock::new(State {
         expectations: vec![],
+        journal: Mutex::new(Journal::new(cli_args.journal_capacity)),
+        scenarios: Scenarios::default(),
+        sources: configuration_files.clone(),
     }));
 
-    let load_configurations = join_all(
-        cli_args
-            .configuration_files
-            .into_iter()
-            .map(|configuration| load_configuration_file(state.clone(), configuration)),
-    );
+    tokio::task::spawn(collect_expired_expectations(
+        state.clone(),
+        EXPIRED_COLLECTION_INTERVAL,
+    ));
+
+    // Watchers start once the initial load is done, from modification times taken before it
+    let watches: Vec<ConfigurationWatch> = if cli_args.watch {
+        configuration_files
+            .iter()
+            .cloned()
+            .map(ConfigurationWatch::new)
+            .collect()
+    } else {
+        vec![]
+    };
+    let loaded_state = state.clone();
+    let load_configurations = async move {
+        load_configuration_files(loaded_state.clone(), configuration_files).await;
+        for watch in watches {
+            tokio::task::spawn(watch.watch(loaded_state.clone(), DEFAULT_WATCH_INTERVAL));
+        }
+    };
 
     if cli_args.wait {
         load_configurations.await;
@@ -56,34 +106,55 @@ async fn main() -> Result<(), Error> {
         tokio::task::spawn(load_configurations);
     }
 
-    let mock_server_context = MockServerContext {
-        http_bind: cli_args.http_bind,
-        state: state.clone(),
+    let tls = match (&cli_args.tls_cert, &cli_args.tls_key) {
+        (Some(cert), Some(key)) => Some(TlsContext::from_pem_files(cert, key)?),
+        _ if cli_args.tls_self_signed => Some(TlsContext::self_signed(&self_signed_names(
+            &cli_args.http_bind,
+        ))?),
+        _ => None,
+    };
+    let tls = match (tls, &cli_args.tls_client_ca) {
+        (Some(tls), Some(ca_file)) => {
+            let ca_bundle = fs::read_to_string(ca_file)
+                .context(format!("Error reading client CA bundle {}", ca_file))?;
+            Some(tls.with_client_ca(&ca_bundle, cli_args.tls_client_auth)?)
+        }
+        (None, Some(_)) => {
+            return Err(anyhow!(
+                "--tls-client-ca needs https, use --tls-cert or --tls-self-signed"
+            ))
+        }
+        (tls, None) => tls,
     };
 
     let admin_server_context = AdminServerContext {
         http_bind: cli_args.admin_http_bind,
+        state: state.clone(),
+        tls_certificate: tls.as_ref().map(|tls| tls.certificate.clone()),
+    };
+
+    let mock_server_context = MockServerContext {
+        http_bind: cli_args.http_bind,
         state,
+        diagnostics: cli_args.diagnostics,
+        tls,
+        proxy_to: cli_args.proxy_to,
     };
 
     start_servers(mock_server_context, admin_server_context).await
 }
 
-async fn load_configuration_file(
-    state: SharedState,
-    configuration_name: String,
-) -> Result<(), Error> {
-    let configuration = fs::read_to_string(configuration_name.as_str())
-        .context(format!("Error reading file {} content", configuration_name))?;
-    match load_dhall_expectation(configuration_name.clone(), configuration)
-        .and_then(|expectations| add_expectations_in_state(state, expectations))
-        .await
-    {
-        Ok(()) => info!("Configuration {} loaded", configuration_name),
-        Err(e) => warn!(
-            "Error loading configuration {} : {:#}",
-            configuration_name, e
-        ),
-    };
-    Ok(())
+/// Local names plus the bound address when the mock server doesn't listen on all interfaces.
+fn self_signed_names(http_bind: &str) -> Vec<String> {
+    let mut names = vec![
+        String::from("localhost"),
+        String::from("127.0.0.1"),
+        String::from("::1"),
+    ];
+    if let Ok(addr) = http_bind.parse::<SocketAddr>() {
+        if !addr.ip().is_unspecified() && !addr.ip().is_loopback() {
+            names.push(addr.ip().to_string());
+        }
+    }
+    names
 }
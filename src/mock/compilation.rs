@@ -1,8 +1,134 @@
+use std::fmt;
+use std::iter;
+
 use super::model::Expectation;
-use anyhow::{Context, Error};
+use super::sequence::ResponseSequence;
+use anyhow::{anyhow, Context, Error};
+use serde::de::{
+    DeserializeOwned, Deserializer, EnumAccess, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+use serde::Deserialize;
+
+/// Prefix of the headers read by the mock connection to rewrite a response (status reason, fault).
+const INTERNAL_HEADER_PREFIX: &str = "x-dhall-mock-";
 
 pub fn compile_configuration(configuration_content: &str) -> Result<Vec<Expectation>, Error> {
-    serde_dhall::from_str(configuration_content)
+    compile_dhall(configuration_content).and_then(check_response_headers)
+}
+
+/// Error of a configuration file compiling to something else than a list, ex: a record shared by
+/// the configuration files.
+#[derive(Debug, thiserror::Error)]
+#[error("The configuration isn't a list of expectations")]
+pub struct NotExpectationList;
+
+/// Compile the configuration file `configuration_file`, its relative imports are resolved from
+/// the file folder. A file that isn't a list fails with `NotExpectationList`.
+pub fn compile_configuration_file(configuration_file: &str) -> Result<Vec<Expectation>, Error> {
+    serde_dhall::from_file(configuration_file)
+        .parse::<ExpectationList>()
+        .context("Error parsing shall configuration")
+        .and_then(|list| list.0.ok_or_else(|| Error::new(NotExpectationList)))
+        .and_then(check_response_headers)
+}
+
+/// Expectations of a configuration file, `None` when the file isn't a list.
+struct ExpectationList(Option<Vec<Expectation>>);
+
+impl<'de> Deserialize<'de> for ExpectationList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ExpectationListVisitor)
+    }
+}
+
+struct ExpectationListVisitor;
+
+impl<'de> Visitor<'de> for ExpectationListVisitor {
+    type Value = ExpectationList;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of expectations")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut expectations = vec![];
+        while let Some(expectation) = seq.next_element()? {
+            expectations.push(expectation);
+        }
+        Ok(ExpectationList(Some(expectations)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        IgnoredAny.visit_map(map).map(|_| ExpectationList(None))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        IgnoredAny.visit_enum(data).map(|_| ExpectationList(None))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        IgnoredAny::deserialize(deserializer).map(|_| ExpectationList(None))
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(ExpectationList(None))
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(ExpectationList(None))
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(ExpectationList(None))
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(ExpectationList(None))
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(ExpectationList(None))
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(ExpectationList(None))
+    }
+}
+
+/// Refuse the response headers named like the internal headers, they would rewrite the response.
+fn check_response_headers(expectations: Vec<Expectation>) -> Result<Vec<Expectation>, Error> {
+    for expectation in expectations.iter() {
+        let responses = iter::once(&expectation.response).chain(
+            expectation
+                .responses
+                .iter()
+                .flat_map(ResponseSequence::responses),
+        );
+        for response in responses {
+            let names = response
+                .headers
+                .iter()
+                .map(|(name, _)| name)
+                .chain(response.header_templates.iter().map(|(name, _)| name));
+            for name in names {
+                if name
+                    .to_ascii_lowercase()
+                    .starts_with(INTERNAL_HEADER_PREFIX)
+                {
+                    return Err(anyhow!(
+                        "Header {} is reserved, response headers can't start with {}",
+                        name,
+                        INTERNAL_HEADER_PREFIX
+                    ));
+                }
+            }
+        }
+    }
+    Ok(expectations)
+}
+
+pub fn compile_dhall<T: DeserializeOwned>(content: &str) -> Result<T, Error> {
+    serde_dhall::from_str(content)
         .parse()
         .context("Error parsing shall configuration")
 }
@@ -10,8 +136,7 @@ pub fn compile_configuration(configuration_content: &str) -> Result<Vec<Expectat
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::mock::model::{Expectation, HttpMethod, HttpRequest, HttpResponse};
-    use std::collections::HashMap;
+    use crate::mock::model::{Expectation, HttpMethod, HttpRequest, HttpResponse, RequestPath};
 
     #[test]
     fn test_compile_configuration() {
@@ -19,7 +144,7 @@ mod test {
             let Mock = ./dhall/Mock/package.dhall
 
             let expectations = [ { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
-                                                                , path    = Some "/greet/pwet"
+                                                                , path    = Some (Mock.Path.Exact "/greet/pwet")
                                                                 }
                                , response = Mock.HttpResponse::{ statusCode   = Mock.statusOK
                                                                , body         = Some "Hello, pwet !"
@@ -30,18 +155,32 @@ mod test {
         "###;
 
         let expected = vec![Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: HttpRequest {
                 method: Some(HttpMethod::GET),
-                path: Some("/greet/pwet".to_string()),
+                path: Some(RequestPath::Exact("/greet/pwet".to_string())),
                 body: None,
                 params: vec![],
-                headers: HashMap::new(),
+                headers: vec![],
+                matcher: None,
             },
             response: HttpResponse {
                 status_code: Some(200),
                 status_reason: None,
                 body: Some("Hello, pwet !".to_string()),
-                headers: HashMap::new(),
+                headers: vec![],
+                body_json: None,
+                body_template: None,
+                header_templates: vec![],
+                delay: None,
+                fault: None,
+                proxy: None,
             },
         }];
 
@@ -55,7 +194,7 @@ mod test {
         let data = r###"
             let Mock = ./dhall/Mock/package.dhall
             in { request  = { method  = Some Mock.HttpMethod.GET
-                             , path    = Some "/greet/pwet"
+                             , path    = Some (Mock.Path.Exact "/greet/pwet")
                              }
                 , response = { statusCode   = Some "200"
                              , statusReason = None
@@ -66,4 +205,54 @@ mod test {
 
         assert!(compile_configuration(data).is_err())
     }
+
+    #[test]
+    fn test_compile_configuration_fail_on_invalid_regex() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in [ { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Matching (Mock.StringMatcher.Regex "/users/(")) }
+                 , response = Mock.HttpResponse::{ statusCode = Mock.statusOK }
+                 }
+               ]
+        "###;
+
+        let error = compile_configuration(data).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("regex parse error"))
+    }
+
+    #[test]
+    fn test_compile_configuration_fail_on_internal_header() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in [ Mock.Expectation::{ request   = Mock.HttpRequest::{=}
+                                   , responses = Some (Mock.ResponseSequence.Cyclic
+                                                  [ Mock.HttpResponse::{=}
+                                                  , Mock.HttpResponse::{ headers = [ { mapKey = "X-Dhall-Mock-Fault", mapValue = "reset" } ] }
+                                                  ])
+                                   }
+               ]
+        "###;
+
+        let error = compile_configuration(data).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("Header X-Dhall-Mock-Fault is reserved"))
+    }
+
+    #[test]
+    fn test_compile_configuration_file_not_expectation_list() {
+        let dir = std::env::temp_dir().join(format!("dhall-mock-list-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let record = dir.join("common.dhall");
+        std::fs::write(&record, r#"{ invoices = "/billing/invoices" }"#).unwrap();
+        let invalid = dir.join("invalid.dhall");
+        std::fs::write(&invalid, r#"[ { invoices = "/billing/invoices" } ]"#).unwrap();
+
+        let error = compile_configuration_file(&record.to_string_lossy()).unwrap_err();
+        assert!(error.is::<NotExpectationList>());
+        let error = compile_configuration_file(&invalid.to_string_lossy()).unwrap_err();
+        assert!(!error.is::<NotExpectationList>());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,124 @@
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Standard normal quantile of the 99th percentile.
+const P99_QUANTILE: f64 = 2.326_347_874;
+
+/// Time waited before sending a response, in milliseconds.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(try_from = "DelayDefinition")]
+pub enum Delay {
+    Fixed(u64),
+    /// Any duration between `min` and `max` with the same probability
+    Uniform {
+        min: u64,
+        max: u64,
+    },
+    /// Log-normal distribution, half of the delays are below `median` and 99% below `p99`
+    LogNormal {
+        median: u64,
+        p99: u64,
+    },
+}
+
+#[derive(Deserialize)]
+enum DelayDefinition {
+    Fixed(u64),
+    Uniform { min: u64, max: u64 },
+    LogNormal { median: u64, p99: u64 },
+}
+
+impl TryFrom<DelayDefinition> for Delay {
+    type Error = String;
+
+    fn try_from(definition: DelayDefinition) -> Result<Self, Self::Error> {
+        match definition {
+            DelayDefinition::Fixed(delay) => Ok(Delay::Fixed(delay)),
+            DelayDefinition::Uniform { min, max } if min > max => Err(format!(
+                "Uniform delay min {}ms is above max {}ms",
+                min, max
+            )),
+            DelayDefinition::Uniform { min, max } => Ok(Delay::Uniform { min, max }),
+            DelayDefinition::LogNormal { median, .. } if median == 0 => {
+                Err(String::from("Log-normal delay median must be above 0ms"))
+            }
+            DelayDefinition::LogNormal { median, p99 } if p99 < median => Err(format!(
+                "Log-normal delay p99 {}ms is below median {}ms",
+                p99, median
+            )),
+            DelayDefinition::LogNormal { median, p99 } => Ok(Delay::LogNormal { median, p99 }),
+        }
+    }
+}
+
+impl Delay {
+    /// Draw the duration to wait for one response.
+    pub fn sample(&self) -> Duration {
+        let mut rng = rand::thread_rng();
+        let millis = match *self {
+            Delay::Fixed(delay) => delay,
+            Delay::Uniform { min, max } => rng.gen_range(min, max + 1),
+            Delay::LogNormal { median, p99 } => {
+                let mu = (median as f64).ln();
+                let sigma = ((p99 as f64).ln() - mu) / P99_QUANTILE;
+                (mu + sigma * standard_normal(&mut rng)).exp().round() as u64
+            }
+        };
+        Duration::from_millis(millis)
+    }
+}
+
+/// Box-Muller transform of two uniform samples.
+fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_fixed_and_uniform_delays() {
+        assert_eq!(Duration::from_millis(150), Delay::Fixed(150).sample());
+        for _ in 0..100 {
+            let delay = Delay::Uniform { min: 10, max: 20 }.sample();
+            assert!(delay >= Duration::from_millis(10) && delay <= Duration::from_millis(20));
+        }
+        assert_eq!(
+            Duration::from_millis(5),
+            Delay::Uniform { min: 5, max: 5 }.sample()
+        );
+    }
+
+    #[test]
+    fn test_sample_log_normal_percentiles() {
+        let delay = Delay::LogNormal {
+            median: 100,
+            p99: 1000,
+        };
+        let mut samples: Vec<Duration> = (0..10_000).map(|_| delay.sample()).collect();
+        samples.sort();
+
+        let median = samples[5_000].as_millis();
+        let p99 = samples[9_900].as_millis();
+        assert!((80..=120).contains(&median), "median {}ms", median);
+        assert!((700..=1400).contains(&p99), "p99 {}ms", p99);
+    }
+
+    #[test]
+    fn test_refuse_invalid_delays() {
+        assert!(Delay::try_from(DelayDefinition::Uniform { min: 20, max: 10 }).is_err());
+        assert!(Delay::try_from(DelayDefinition::LogNormal { median: 0, p99: 10 }).is_err());
+        assert!(Delay::try_from(DelayDefinition::LogNormal {
+            median: 100,
+            p99: 10
+        })
+        .is_err());
+    }
+}
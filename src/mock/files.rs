@@ -0,0 +1,222 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Context, Error};
+use futures::future::join_all;
+use futures::TryFutureExt;
+use log::{info, warn};
+
+use super::compilation::NotExpectationList;
+use super::service::{add_expectations_in_state, load_dhall_expectation_file, SharedState};
+
+const DHALL_EXTENSION: &str = "dhall";
+/// Entry point of a dhall library (ex: `Mock/package.dhall`), never an expectations list
+const PACKAGE_FILE: &str = "package.dhall";
+
+/// Expand the configuration arguments into configuration files, in load order.
+///
+/// A directory is replaced by all the `.dhall` files it contains recursively, except the
+/// `package.dhall` library files, and a glob pattern (ex: `mocks/**/*.dhall`) by the files it
+/// matches, both sorted by path.
+/// Arguments order is kept and a file is only loaded once, at its first position.
+pub fn configuration_files(arguments: &[String]) -> Result<Vec<String>, Error> {
+    let mut files: Vec<String> = vec![];
+    let mut loaded: Vec<PathBuf> = vec![];
+    for argument in arguments {
+        let expanded = expand(argument)?;
+        if expanded.is_empty() {
+            warn!("No configuration file found for {}", argument);
+        }
+        for file in expanded {
+            let normalized = normalize(&file);
+            if !loaded.contains(&normalized) {
+                loaded.push(normalized);
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Compile all the configuration files at once but add their expectations in the files order,
+/// so the first matching expectation doesn't depend on compilation times.
+/// The files that aren't expectations lists (ex: shared records) are skipped and reported apart
+/// from the failed ones.
+pub async fn load_configuration_files(state: SharedState, configuration_names: Vec<String>) {
+    let configurations = join_all(
+        configuration_names
+            .iter()
+            .cloned()
+            .map(load_dhall_expectation_file),
+    )
+    .await;
+
+    let mut loaded_files = 0;
+    let mut loaded_expectations = 0;
+    let mut skipped_files: Vec<&str> = vec![];
+    let mut failed_files: Vec<&str> = vec![];
+    for (configuration_name, configuration) in configuration_names.iter().zip(configurations) {
+        let expectations = configuration
+            .as_ref()
+            .map(|c| c.expectations.len())
+            .unwrap_or(0);
+        match futures::future::ready(configuration)
+            .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+            .await
+        {
+            Ok(()) => {
+                loaded_files += 1;
+                loaded_expectations += expectations;
+                info!(
+                    "Configuration {} loaded : {} expectations",
+                    configuration_name, expectations
+                )
+            }
+            Err(e) if e.is::<NotExpectationList>() => {
+                skipped_files.push(configuration_name);
+                warn!(
+                    "Configuration {} skipped : not a list of expectations",
+                    configuration_name
+                )
+            }
+            Err(e) => {
+                failed_files.push(configuration_name);
+                warn!(
+                    "Error loading configuration {} : {:#}",
+                    configuration_name, e
+                )
+            }
+        };
+    }
+    info!(
+        "{}/{} configuration files loaded : {} expectations",
+        loaded_files,
+        configuration_names.len(),
+        loaded_expectations
+    );
+    if !skipped_files.is_empty() {
+        warn!(
+            "{} files skipped, not lists of expectations : {}",
+            skipped_files.len(),
+            skipped_files.join(", ")
+        );
+    }
+    if !failed_files.is_empty() {
+        warn!(
+            "{} configuration files failed : {}",
+            failed_files.len(),
+            failed_files.join(", ")
+        );
+    }
+}
+
+/// `./mocks/users.dhall` and `mocks/users.dhall` are the same file.
+fn normalize(file: &str) -> PathBuf {
+    Path::new(file)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+fn expand(argument: &str) -> Result<Vec<String>, Error> {
+    let path = Path::new(argument);
+    let mut files = if path.is_dir() {
+        let mut files = vec![];
+        dhall_files(path, &mut files).context(format!(
+            "Error reading configuration directory {}",
+            argument
+        ))?;
+        files
+    } else if argument.contains(|c| c == '*' || c == '?' || c == '[') {
+        glob::glob(argument)
+            .map_err(|e| anyhow!("Invalid configuration pattern {} : {}", argument, e))?
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .collect()
+    } else {
+        return Ok(vec![argument.to_string()]);
+    };
+    files.sort();
+    Ok(files
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect())
+}
+
+fn dhall_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dhall_files(&path, files)?;
+        } else if path.extension().map_or(false, |e| e == DHALL_EXTENSION)
+            && path.file_name().map_or(false, |n| n != PACKAGE_FILE)
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn configuration_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dhall-mock-{}-{}", name, std::process::id()));
+        for file in &["b.dhall", "a/z.dhall", "a/y/x.dhall", "a/readme.md"] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "[]").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_expand_directory() {
+        let dir = configuration_dir("directory");
+        let name = dir.to_string_lossy().to_string();
+        fs::create_dir_all(dir.join("Mock")).unwrap();
+        fs::write(dir.join("Mock/package.dhall"), "{=}").unwrap();
+
+        let files = configuration_files(&[name.clone()]).unwrap();
+
+        assert_eq!(
+            vec![
+                format!("{}/a/y/x.dhall", name),
+                format!("{}/a/z.dhall", name),
+                format!("{}/b.dhall", name),
+            ],
+            files
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_glob_pattern_in_arguments_order() {
+        let dir = configuration_dir("glob");
+        let name = dir.to_string_lossy().to_string();
+
+        let files = configuration_files(&[
+            format!("{}/b.dhall", name),
+            format!("{}/**/*.dhall", name),
+            String::from("missing.dhall"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                format!("{}/b.dhall", name),
+                format!("{}/a/y/x.dhall", name),
+                format!("{}/a/z.dhall", name),
+                String::from("missing.dhall"),
+            ],
+            files
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_refuse_invalid_pattern() {
+        assert!(configuration_files(&[String::from("./dhall/***.dhall")]).is_err());
+    }
+}
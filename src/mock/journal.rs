@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+
+use crate::mock::model::{ExpectationEntry, HttpMethod, IncomingRequest};
+use crate::mock::serde as serde_mock;
+
+pub const DEFAULT_JOURNAL_CAPACITY: usize = 1000;
+
+/// Request received by the mock server with the expectation used to answer it.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct JournalEntry {
+    #[serde(with = "serde_mock::rfc3339")]
+    pub timestamp: SystemTime,
+    pub request: IncomingRequest,
+    pub expectation: Option<JournalExpectation>,
+    #[serde(rename = "statusCode")]
+    pub status_code: u16,
+}
+
+/// Expectation answering a recorded request, identified by its `id` and `name`.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct JournalExpectation {
+    pub id: u64,
+    pub name: Option<String>,
+}
+
+impl From<&ExpectationEntry> for JournalExpectation {
+    fn from(entry: &ExpectationEntry) -> Self {
+        JournalExpectation {
+            id: entry.id,
+            name: entry.expectation.name.clone(),
+        }
+    }
+}
+
+/// Last requests received by the mock server, the oldest ones are dropped past `capacity`.
+#[derive(Debug)]
+pub struct Journal {
+    capacity: usize,
+    entries: VecDeque<JournalEntry>,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Journal {
+        Journal {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(&mut self, entry: JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> {
+        self.entries.iter()
+    }
+
+    /// Entries accepted by `filter`, in reception order.
+    pub fn find(&self, filter: &JournalFilter) -> Vec<&JournalEntry> {
+        self.entries.iter().filter(|e| filter.accept(e)).collect()
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal::new(DEFAULT_JOURNAL_CAPACITY)
+    }
+}
+
+/// Journal query, each criteria is optional.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct JournalFilter {
+    pub method: Option<HttpMethod>,
+    pub path: Option<String>,
+    pub matched: Option<bool>,
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+}
+
+impl JournalFilter {
+    /// Read the filter from a query string, ex: `method=GET&path=/users&matched=false&from=2020-05-06T19:52:10Z`.
+    pub fn from_query(query: &str) -> Result<JournalFilter, Error> {
+        let mut filter = JournalFilter::default();
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "method" => filter.method = Some(value.parse().map_err(|e| anyhow!("{}", e))?),
+                "path" => filter.path = Some(value.into_owned()),
+                "matched" => {
+                    filter.matched = Some(
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("matched must be true or false : {}", value))?,
+                    )
+                }
+                "from" => filter.from = Some(parse_timestamp(&value)?),
+                "to" => filter.to = Some(parse_timestamp(&value)?),
+                key => return Err(anyhow!("Unknown request filter {}", key)),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn accept(&self, entry: &JournalEntry) -> bool {
+        self.method
+            .map(|m| m == entry.request.method)
+            .unwrap_or(true)
+            && self
+                .path
+                .as_ref()
+                .map(|p| *p == entry.request.path)
+                .unwrap_or(true)
+            && self
+                .matched
+                .map(|m| m == entry.expectation.is_some())
+                .unwrap_or(true)
+            && self.from.map(|t| entry.timestamp >= t).unwrap_or(true)
+            && self.to.map(|t| entry.timestamp <= t).unwrap_or(true)
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<SystemTime, Error> {
+    humantime::parse_rfc3339_weak(value)
+        .map_err(|e| anyhow!("Invalid rfc3339 timestamp {} : {}", value, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn entry(method: HttpMethod, path: &str, status_code: u16, secs: u64) -> JournalEntry {
+        JournalEntry {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            request: IncomingRequest {
+                method,
+                path: String::from(path),
+                body: String::new(),
+                headers: HashMap::new(),
+                params: HashMap::new(),
+                client_certificate: None,
+            },
+            expectation: None,
+            status_code,
+        }
+    }
+
+    #[test]
+    fn test_drop_oldest_entries() {
+        let mut journal = Journal::new(2);
+        journal.record(entry(HttpMethod::GET, "/1", 404, 1));
+        journal.record(entry(HttpMethod::GET, "/2", 404, 2));
+        journal.record(entry(HttpMethod::GET, "/3", 404, 3));
+
+        let paths: Vec<&str> = journal
+            .find(&JournalFilter::default())
+            .iter()
+            .map(|e| e.request.path.as_str())
+            .collect();
+        assert_eq!(vec!["/2", "/3"], paths);
+
+        journal.clear();
+        assert!(journal.find(&JournalFilter::default()).is_empty());
+    }
+
+    #[test]
+    fn test_filter_entries() {
+        let mut journal = Journal::default();
+        journal.record(entry(HttpMethod::GET, "/users", 404, 1_588_794_730));
+        journal.record(entry(HttpMethod::POST, "/users", 404, 1_588_794_740));
+        journal.record(entry(HttpMethod::POST, "/orders", 404, 1_588_794_750));
+
+        let filter = JournalFilter::from_query(
+            "method=post&path=%2Fusers&matched=false&from=2020-05-06T19:52:10Z&to=2020-05-06T19:52:20Z",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![&entry(HttpMethod::POST, "/users", 404, 1_588_794_740)],
+            journal.find(&filter)
+        );
+
+        let filter = JournalFilter::from_query("matched=true").unwrap();
+        assert!(journal.find(&filter).is_empty());
+    }
+
+    #[test]
+    fn test_refuse_invalid_filter() {
+        assert!(JournalFilter::from_query("method=FETCH").is_err());
+        assert!(JournalFilter::from_query("matched=yes").is_err());
+        assert!(JournalFilter::from_query("from=yesterday").is_err());
+        assert!(JournalFilter::from_query("status=200").is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use std::fmt;
+use std::str::FromStr;
+
+use jsonpath_lib::Compiled;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::mock::model::StringMatcher;
+use crate::mock::serde as serde_mock;
+
+/// How arrays of an expected json document are compared with the incoming ones.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum ArrayMatching {
+    /// Same length, each element matches the element at the same index
+    Strict,
+    /// Same length, each element matches a distinct element at any index
+    Unordered,
+    /// Each expected element matches a distinct element at any index, other elements are ignored
+    Contains,
+}
+
+/// Test if `expected` is a subset of `actual` : objects match if all the expected keys are present
+/// with matching values, arrays are compared according to `arrays`, other values must be equal.
+pub fn is_subset(expected: &Value, actual: &Value, arrays: ArrayMatching) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .map(|actual| is_subset(value, actual, arrays))
+                .unwrap_or(false)
+        }),
+        (Value::Array(expected), Value::Array(actual)) => match arrays {
+            ArrayMatching::Strict => {
+                expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(actual)
+                        .all(|(expected, actual)| is_subset(expected, actual, arrays))
+            }
+            ArrayMatching::Unordered => {
+                expected.len() == actual.len() && match_all_elements(expected, actual, arrays)
+            }
+            ArrayMatching::Contains => match_all_elements(expected, actual, arrays),
+        },
+        (Value::Number(expected), Value::Number(actual)) => expected.as_f64() == actual.as_f64(),
+        (expected, actual) => expected == actual,
+    }
+}
+
+/// Look for a distinct actual element matching each expected element (bipartite matching).
+fn match_all_elements(expected: &[Value], actual: &[Value], arrays: ArrayMatching) -> bool {
+    let candidates: Vec<Vec<usize>> = expected
+        .iter()
+        .map(|expected| {
+            actual
+                .iter()
+                .enumerate()
+                .filter(|(_, actual)| is_subset(expected, actual, arrays))
+                .map(|(index, _)| index)
+                .collect()
+        })
+        .collect();
+
+    let mut assigned: Vec<Option<usize>> = vec![None; actual.len()];
+    (0..expected.len()).all(|element| {
+        let mut visited = vec![false; actual.len()];
+        assign(element, &candidates, &mut assigned, &mut visited)
+    })
+}
+
+fn assign(
+    element: usize,
+    candidates: &[Vec<usize>],
+    assigned: &mut Vec<Option<usize>>,
+    visited: &mut Vec<bool>,
+) -> bool {
+    for &candidate in candidates[element].iter() {
+        if visited[candidate] {
+            continue;
+        }
+        visited[candidate] = true;
+        let free = match assigned[candidate] {
+            None => true,
+            Some(other) => assign(other, candidates, assigned, visited),
+        };
+        if free {
+            assigned[candidate] = Some(element);
+            return true;
+        }
+    }
+    false
+}
+
+/// JSONPath expression compiled at configuration load, ex: `$.order.items[*].sku`.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    source: String,
+    compiled: Compiled,
+}
+
+impl JsonPath {
+    pub fn select<'a>(&self, document: &'a Value) -> Vec<&'a Value> {
+        self.compiled.select(document).unwrap_or_default()
+    }
+}
+
+impl PartialEq for JsonPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Ok(JsonPath {
+            source: source.to_string(),
+            compiled: Compiled::compile(source)
+                .map_err(|e| format!("Invalid JSONPath {} : {}", source, e))?,
+        })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum JsonPathMatcher {
+    Equals(#[serde(with = "serde_mock::json_value")] Value),
+    Exists,
+    Absent,
+    Matches(StringMatcher),
+    GreaterThan(f64),
+    GreaterOrEqual(f64),
+    LessThan(f64),
+    LessOrEqual(f64),
+}
+
+/// Assertion on the values selected by a JSONPath expression, satisfied if any selected value
+/// matches (`Absent` is satisfied if nothing is selected).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct JsonPathPredicate {
+    #[serde(with = "serde_mock::parsed_string")]
+    pub path: JsonPath,
+    pub matcher: JsonPathMatcher,
+}
+
+impl JsonPathPredicate {
+    pub fn test(&self, document: &Value) -> bool {
+        let values = self.path.select(document);
+        let any_number = |test: &dyn Fn(f64) -> bool| {
+            values
+                .iter()
+                .any(|value| value.as_f64().map(|n| test(n)).unwrap_or(false))
+        };
+        match &self.matcher {
+            JsonPathMatcher::Equals(expected) => values.iter().any(|value| {
+                is_subset(expected, value, ArrayMatching::Strict)
+                    && is_subset(value, expected, ArrayMatching::Strict)
+            }),
+            JsonPathMatcher::Exists => !values.is_empty(),
+            JsonPathMatcher::Absent => values.is_empty(),
+            JsonPathMatcher::Matches(matcher) => values.iter().any(|value| {
+                value
+                    .as_str()
+                    .map(|text| matcher.matches(text))
+                    .unwrap_or(false)
+            }),
+            JsonPathMatcher::GreaterThan(limit) => any_number(&|n| n > *limit),
+            JsonPathMatcher::GreaterOrEqual(limit) => any_number(&|n| n >= *limit),
+            JsonPathMatcher::LessThan(limit) => any_number(&|n| n < *limit),
+            JsonPathMatcher::LessOrEqual(limit) => any_number(&|n| n <= *limit),
+        }
+    }
+}
+
+/// Dhall types can't be recursive : a Prelude `JSON.Type` value is folded into a list of nodes
+/// in pre-order, `Array n` is followed by its `n` items and `Object keys` by the value of each key.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum JsonNode {
+    Array(usize),
+    Bool(bool),
+    Double(f64),
+    Integer(i64),
+    Null,
+    Object(Vec<String>),
+    String(String),
+}
+
+/// Rebuild the json document from its pre-order `nodes`, all the nodes must be consumed.
+pub fn from_nodes(nodes: Vec<JsonNode>) -> Result<Value, String> {
+    let mut nodes = nodes.into_iter();
+    let value = JsonNode::next_value(&mut nodes)?;
+    match nodes.next() {
+        None => Ok(value),
+        Some(_) => Err(String::from("JSON nodes left after the root value")),
+    }
+}
+
+impl JsonNode {
+    fn next_value(nodes: &mut impl Iterator<Item = JsonNode>) -> Result<Value, String> {
+        match nodes.next() {
+            Some(JsonNode::Array(count)) => (0..count)
+                .map(|_| JsonNode::next_value(nodes))
+                .collect::<Result<Vec<Value>, String>>()
+                .map(Value::Array),
+            Some(JsonNode::Bool(b)) => Ok(Value::Bool(b)),
+            Some(JsonNode::Double(d)) => serde_json::Number::from_f64(d)
+                .map(Value::Number)
+                .ok_or_else(|| format!("{} isn't a valid JSON number", d)),
+            Some(JsonNode::Integer(i)) => Ok(Value::from(i)),
+            Some(JsonNode::Null) => Ok(Value::Null),
+            Some(JsonNode::Object(keys)) => keys
+                .into_iter()
+                .map(|key| JsonNode::next_value(nodes).map(|value| (key, value)))
+                .collect::<Result<serde_json::Map<String, Value>, String>>()
+                .map(Value::Object),
+            Some(JsonNode::String(s)) => Ok(Value::String(s)),
+            None => Err(String::from("Missing JSON nodes")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_object_subset() {
+        let actual = json!({ "id": "4c30413c", "amount": 100, "createdAt": "2020-04-05T08:00:34", "user": { "name": "robert", "age": 42 } });
+
+        assert!(is_subset(
+            &json!({ "amount": 100.0, "user": { "name": "robert" } }),
+            &actual,
+            ArrayMatching::Strict
+        ));
+        assert!(!is_subset(
+            &json!({ "amount": 101 }),
+            &actual,
+            ArrayMatching::Strict
+        ));
+        assert!(!is_subset(
+            &json!({ "user": { "email": "robert@mail.com" } }),
+            &actual,
+            ArrayMatching::Strict
+        ));
+        assert!(!is_subset(&json!([]), &actual, ArrayMatching::Strict));
+    }
+
+    #[test]
+    fn test_strict_arrays() {
+        let actual = json!({ "items": [ { "sku": "ABC", "qty": 1 }, { "sku": "DEF", "qty": 2 } ] });
+
+        assert!(is_subset(
+            &json!({ "items": [ { "sku": "ABC" }, { "sku": "DEF" } ] }),
+            &actual,
+            ArrayMatching::Strict
+        ));
+        assert!(!is_subset(
+            &json!({ "items": [ { "sku": "DEF" }, { "sku": "ABC" } ] }),
+            &actual,
+            ArrayMatching::Strict
+        ));
+        assert!(!is_subset(
+            &json!({ "items": [ { "sku": "ABC" } ] }),
+            &actual,
+            ArrayMatching::Strict
+        ));
+    }
+
+    #[test]
+    fn test_unordered_arrays() {
+        let actual =
+            json!([ { "sku": "ABC", "qty": 1 }, { "sku": "ABC", "qty": 2 }, { "sku": "DEF" } ]);
+
+        assert!(is_subset(
+            &json!([ { "sku": "DEF" }, { "sku": "ABC", "qty": 2 }, { "sku": "ABC" } ]),
+            &actual,
+            ArrayMatching::Unordered
+        ));
+        assert!(!is_subset(
+            &json!([ { "sku": "DEF" }, { "sku": "ABC" } ]),
+            &actual,
+            ArrayMatching::Unordered
+        ));
+        assert!(!is_subset(
+            &json!([ { "sku": "DEF" }, { "sku": "DEF" }, { "sku": "ABC" } ]),
+            &actual,
+            ArrayMatching::Unordered
+        ));
+    }
+
+    #[test]
+    fn test_contains_arrays() {
+        let actual = json!({ "tags": [ "a", "b", "c" ] });
+
+        assert!(is_subset(
+            &json!({ "tags": [ "c", "a" ] }),
+            &actual,
+            ArrayMatching::Contains
+        ));
+        assert!(!is_subset(
+            &json!({ "tags": [ "a", "a" ] }),
+            &actual,
+            ArrayMatching::Contains
+        ));
+        assert!(!is_subset(
+            &json!({ "tags": [ "d" ] }),
+            &actual,
+            ArrayMatching::Contains
+        ));
+    }
+
+    fn predicate(path: &str, matcher: JsonPathMatcher) -> JsonPathPredicate {
+        JsonPathPredicate {
+            path: path.parse().unwrap(),
+            matcher,
+        }
+    }
+
+    #[test]
+    fn test_json_path_predicates() {
+        let document = json!({
+            "amount": 120,
+            "order": { "id": "A-42", "items": [ { "sku": "ABC", "qty": 1 }, { "sku": "DEF", "qty": 3 } ] }
+        });
+
+        assert!(predicate(
+            "$.order.items[*].sku",
+            JsonPathMatcher::Equals(json!("ABC"))
+        )
+        .test(&document));
+        assert!(!predicate(
+            "$.order.items[*].sku",
+            JsonPathMatcher::Equals(json!("XYZ"))
+        )
+        .test(&document));
+        assert!(predicate("$.amount", JsonPathMatcher::GreaterThan(100.0)).test(&document));
+        assert!(!predicate("$.amount", JsonPathMatcher::LessOrEqual(100.0)).test(&document));
+        assert!(
+            predicate("$.order.items[*].qty", JsonPathMatcher::GreaterOrEqual(3.0)).test(&document)
+        );
+        assert!(predicate("$.order.id", JsonPathMatcher::Exists).test(&document));
+        assert!(!predicate("$.order.id", JsonPathMatcher::Absent).test(&document));
+        assert!(predicate("$.order.discount", JsonPathMatcher::Absent).test(&document));
+        assert!(predicate(
+            "$.order.id",
+            JsonPathMatcher::Matches(StringMatcher::Regex("[A-Z]-[0-9]+".parse().unwrap()))
+        )
+        .test(&document));
+        assert!(
+            !predicate("$.amount", JsonPathMatcher::Matches(StringMatcher::Any)).test(&document)
+        );
+    }
+
+    #[test]
+    fn test_invalid_json_path() {
+        assert!("$.order[".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn test_refuse_incomplete_json_nodes() {
+        assert!(from_nodes(vec![JsonNode::Array(2), JsonNode::Null]).is_err());
+        assert!(from_nodes(vec![JsonNode::Null, JsonNode::Null]).is_err());
+        assert!(from_nodes(vec![JsonNode::Double(f64::NAN)]).is_err());
+        assert_eq!(
+            Ok(json!({ "a": [1, "b"] })),
+            from_nodes(vec![
+                JsonNode::Object(vec![String::from("a")]),
+                JsonNode::Array(2),
+                JsonNode::Integer(1),
+                JsonNode::String(String::from("b")),
+            ])
+        );
+    }
+}
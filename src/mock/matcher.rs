@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mock::model::{
+    HttpMethod, HttpRequest, IncomingRequest, Predicate, RequestBody, RequestPath, StringMatcher,
+};
+
+/// Leaf of a request matcher tree.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum Criterion {
+    Method(HttpMethod),
+    Path(RequestPath),
+    Header {
+        #[serde(alias = "mapKey")]
+        name: String,
+        #[serde(alias = "mapValue")]
+        value: StringMatcher,
+    },
+    Param {
+        key: String,
+        value: StringMatcher,
+    },
+    Body(RequestBody),
+    /// Subject of the client certificate, refused without client certificate
+    ClientSubject(StringMatcher),
+    /// Any subject alternative name of the client certificate
+    ClientSAN(StringMatcher),
+}
+
+/// Boolean combination of request criteria, tested in addition to the other request criteria.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum RequestMatcher {
+    AllOf(Vec<RequestMatcher>),
+    AnyOf(Vec<RequestMatcher>),
+    Not(Box<RequestMatcher>),
+    Criterion(Criterion),
+}
+
+/// Dhall types can't be recursive : the tree built in the configuration is folded into
+/// a list of nodes in pre-order, `AllOf n` and `AnyOf n` are followed by their `n` children.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum RequestMatcherNode {
+    AllOf(usize),
+    AnyOf(usize),
+    Not,
+    Criterion(Criterion),
+}
+
+impl RequestMatcher {
+    /// Rebuild the tree from its pre-order `nodes`, all the nodes must be consumed.
+    pub fn from_nodes(nodes: Vec<RequestMatcherNode>) -> Result<RequestMatcher, String> {
+        let mut nodes = nodes.into_iter();
+        let matcher = RequestMatcher::next_tree(&mut nodes)?;
+        match nodes.next() {
+            None => Ok(matcher),
+            Some(_) => Err(String::from("Request matcher nodes left after the root")),
+        }
+    }
+
+    fn next_tree(
+        nodes: &mut impl Iterator<Item = RequestMatcherNode>,
+    ) -> Result<RequestMatcher, String> {
+        match nodes.next() {
+            Some(RequestMatcherNode::AllOf(count)) => Ok(RequestMatcher::AllOf(
+                RequestMatcher::next_trees(nodes, count)?,
+            )),
+            Some(RequestMatcherNode::AnyOf(count)) => Ok(RequestMatcher::AnyOf(
+                RequestMatcher::next_trees(nodes, count)?,
+            )),
+            Some(RequestMatcherNode::Not) => Ok(RequestMatcher::Not(Box::new(
+                RequestMatcher::next_tree(nodes)?,
+            ))),
+            Some(RequestMatcherNode::Criterion(criterion)) => {
+                Ok(RequestMatcher::Criterion(criterion))
+            }
+            None => Err(String::from("Missing request matcher nodes")),
+        }
+    }
+
+    fn next_trees(
+        nodes: &mut impl Iterator<Item = RequestMatcherNode>,
+        count: usize,
+    ) -> Result<Vec<RequestMatcher>, String> {
+        (0..count)
+            .map(|_| RequestMatcher::next_tree(nodes))
+            .collect()
+    }
+}
+
+impl Predicate for Criterion {
+    fn test(&self, _expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        match self {
+            Criterion::Method(method) => *method == req.method,
+            Criterion::Path(path) => path.matches(&req.path),
+            Criterion::Header { name, value } => req.has_header(name, value),
+            Criterion::Param { key, value } => req.has_param(key, value),
+            Criterion::Body(body) => body.matches(&req.body),
+            Criterion::ClientSubject(subject) => req
+                .client_certificate
+                .as_ref()
+                .map(|c| subject.matches(&c.subject))
+                .unwrap_or(false),
+            Criterion::ClientSAN(san) => req
+                .client_certificate
+                .as_ref()
+                .map(|c| c.subject_alt_names.iter().any(|name| san.matches(name)))
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl Predicate for RequestMatcher {
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        match self {
+            RequestMatcher::AllOf(matchers) => matchers.iter().all(|m| m.test(expected, req)),
+            RequestMatcher::AnyOf(matchers) => matchers.iter().any(|m| m.test(expected, req)),
+            RequestMatcher::Not(matcher) => !matcher.test(expected, req),
+            RequestMatcher::Criterion(criterion) => criterion.test(expected, req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::model::{Expectation, HttpRequest};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_rebuild_tree_from_nodes() {
+        let nodes = vec![
+            RequestMatcherNode::AllOf(2),
+            RequestMatcherNode::AnyOf(2),
+            RequestMatcherNode::Criterion(Criterion::Path(RequestPath::Exact(String::from("/a")))),
+            RequestMatcherNode::Criterion(Criterion::Path(RequestPath::Exact(String::from("/b")))),
+            RequestMatcherNode::Not,
+            RequestMatcherNode::Criterion(Criterion::Method(HttpMethod::DELETE)),
+        ];
+
+        assert_eq!(
+            Ok(RequestMatcher::AllOf(vec![
+                RequestMatcher::AnyOf(vec![
+                    RequestMatcher::Criterion(Criterion::Path(RequestPath::Exact(String::from(
+                        "/a"
+                    )))),
+                    RequestMatcher::Criterion(Criterion::Path(RequestPath::Exact(String::from(
+                        "/b"
+                    )))),
+                ]),
+                RequestMatcher::Not(Box::new(RequestMatcher::Criterion(Criterion::Method(
+                    HttpMethod::DELETE
+                )))),
+            ])),
+            RequestMatcher::from_nodes(nodes)
+        );
+    }
+
+    #[test]
+    fn test_refuse_incomplete_nodes() {
+        assert!(RequestMatcher::from_nodes(vec![
+            RequestMatcherNode::AllOf(2),
+            RequestMatcherNode::Criterion(Criterion::Method(HttpMethod::GET)),
+        ])
+        .is_err());
+        assert!(RequestMatcher::from_nodes(vec![
+            RequestMatcherNode::Criterion(Criterion::Method(HttpMethod::GET)),
+            RequestMatcherNode::Criterion(Criterion::Method(HttpMethod::POST)),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_deserialize_request_matcher() {
+        let request: HttpRequest = serde_dhall::from_str(
+            r###"
+                let Mock = ./dhall/Mock/package.dhall
+                in Mock.HttpRequest::{ matcher = Mock.buildMatcher
+                                                   ( Mock.allOf [ Mock.anyOf [ Mock.criterion (Mock.Criterion.Path (Mock.Path.Exact "/a"))
+                                                                             , Mock.criterion (Mock.Criterion.Path (Mock.Path.Exact "/b"))
+                                                                             ]
+                                                                , Mock.not (Mock.criterion (Mock.Criterion.Header { mapKey = "X-Debug", mapValue = Mock.StringMatcher.Any }))
+                                                                ]
+                                                   )
+                                     }
+            "###,
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            Some(RequestMatcher::AllOf(vec![
+                RequestMatcher::AnyOf(vec![
+                    RequestMatcher::Criterion(Criterion::Path(RequestPath::Exact(String::from(
+                        "/a"
+                    )))),
+                    RequestMatcher::Criterion(Criterion::Path(RequestPath::Exact(String::from(
+                        "/b"
+                    )))),
+                ]),
+                RequestMatcher::Not(Box::new(RequestMatcher::Criterion(Criterion::Header {
+                    name: String::from("X-Debug"),
+                    value: StringMatcher::Any
+                }))),
+            ])),
+            request.matcher
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let request: HttpRequest = serde_dhall::from_str(
+            r###"
+                let Mock = ./dhall/Mock/package.dhall
+                in Mock.HttpRequest::{ matcher = Mock.buildMatcher (Mock.not (Mock.criterion (Mock.Criterion.Method Mock.HttpMethod.GET))) }
+            "###,
+        )
+        .parse()
+        .unwrap();
+
+        let json = serde_json::to_string(&request).unwrap();
+
+        assert_eq!(request, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_request_without_matcher() {
+        let request: HttpRequest = serde_dhall::from_str(
+            r###"
+                let Mock = ./dhall/Mock/package.dhall
+                in Mock.HttpRequest::{=}
+            "###,
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(None, request.matcher);
+    }
+
+    #[test]
+    fn test_evaluate_request_matcher() {
+        let expectation: Expectation = serde_dhall::from_str(
+            r###"
+                let Mock = ./dhall/Mock/package.dhall
+                in { request  = Mock.HttpRequest::{ matcher = Mock.buildMatcher
+                                                                ( Mock.allOf [ Mock.anyOf [ Mock.criterion (Mock.Criterion.Path (Mock.Path.Exact "/a"))
+                                                                                          , Mock.criterion (Mock.Criterion.Param { key = "page", value = Mock.StringMatcher.Any })
+                                                                                          ]
+                                                                             , Mock.not (Mock.criterion (Mock.Criterion.Header { mapKey = "X-Debug", mapValue = Mock.StringMatcher.Any }))
+                                                                             ]
+                                                                )
+                                                  }
+                   , response = Mock.HttpResponse::{=}
+                   }
+            "###,
+        )
+        .parse()
+        .unwrap();
+        let request = |path: &str, params: &[&str], headers: &[&str]| IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from(path),
+            body: String::new(),
+            params: params
+                .iter()
+                .map(|k| (k.to_string(), vec![String::from("1")].into_iter().collect()))
+                .collect(),
+            headers: headers
+                .iter()
+                .map(|k| (k.to_string(), String::from("true")))
+                .collect::<HashMap<String, String>>(),
+            client_certificate: None,
+        };
+
+        assert!(expectation.test(&request("/a", &[], &[])));
+        assert!(expectation.test(&request("/c", &["page"], &["Accept"])));
+        assert!(!expectation.test(&request("/c", &[], &[])));
+        assert!(!expectation.test(&request("/a", &[], &["x-debug"])));
+    }
+}
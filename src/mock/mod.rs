@@ -1,4 +1,15 @@
 mod compilation;
+pub mod delay;
+pub mod files;
+pub mod journal;
+pub mod json;
+pub mod matcher;
 pub mod model;
+pub mod scenario;
+pub mod sequence;
 pub mod serde;
 pub mod service;
+pub mod template;
+pub mod verification;
+pub mod watch;
+pub mod xml;
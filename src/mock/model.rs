@@ -1,20 +1,65 @@
+use hyper::Uri;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::collections::HashSet;
-
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::mock::delay::Delay;
+use crate::mock::json::{self, ArrayMatching, JsonPathPredicate};
+use crate::mock::matcher::RequestMatcher;
+use crate::mock::scenario::{ScenarioStep, Scenarios};
+use crate::mock::sequence::ResponseSequence;
 use crate::mock::serde as serde_mock;
+use crate::mock::template::Template;
+use crate::mock::xml::{XPathBody, XmlDocument};
 
 pub type QueryParams = HashMap<String, HashSet<String>>;
 
-#[derive(Debug, Clone)]
+pub type Headers = Vec<(String, String)>;
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
 pub struct IncomingRequest {
     pub method: HttpMethod,
     pub path: String,
     pub body: String,
     pub headers: HashMap<String, String>,
     pub params: QueryParams,
+    /// Certificate presented by the client on a mutual tls connection
+    #[serde(rename = "clientCertificate")]
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+/// Identity read from a client certificate.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ClientCertificate {
+    /// Distinguished name, ex: `CN=partner-a, O=Acme`
+    pub subject: String,
+    /// Dns names, ip addresses, emails and uris of the subject alternative names
+    #[serde(rename = "subjectAltNames")]
+    pub subject_alt_names: Vec<String>,
+}
+
+impl IncomingRequest {
+    /// Test if a value of the query param `key` is accepted by `matcher`.
+    pub fn has_param(&self, key: &str, matcher: &StringMatcher) -> bool {
+        self.params
+            .get(key)
+            .map(|values| values.iter().any(|v| matcher.matches(v)))
+            .unwrap_or(false)
+    }
+
+    /// Test if a value of the header `name` (case insensitive) is accepted by `matcher`.
+    pub fn has_header(&self, name: &str, matcher: &StringMatcher) -> bool {
+        self.headers
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case(name) && matcher.matches(v))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
@@ -30,24 +75,307 @@ pub enum HttpMethod {
     TRACE,
 }
 
+impl FromStr for HttpMethod {
+    type Err = String;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        match method.to_ascii_uppercase().as_str() {
+            "CONNECT" => Ok(HttpMethod::CONNECT),
+            "DELETE" => Ok(HttpMethod::DELETE),
+            "GET" => Ok(HttpMethod::GET),
+            "HEAD" => Ok(HttpMethod::HEAD),
+            "OPTIONS" => Ok(HttpMethod::OPTIONS),
+            "PATCH" => Ok(HttpMethod::PATCH),
+            "POST" => Ok(HttpMethod::POST),
+            "PUT" => Ok(HttpMethod::PUT),
+            "TRACE" => Ok(HttpMethod::TRACE),
+            method => Err(format!("{} isn't a http method", method)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum RequestBody {
+    #[serde(alias = "JSONValue")]
     JSON {
-        #[serde(with = "serde_mock::json_string")]
+        #[serde(with = "serde_mock::json_value")]
+        json: Value,
+    },
+    #[serde(alias = "JSONSubsetValue")]
+    JSONSubset {
+        #[serde(with = "serde_mock::json_value")]
         json: Value,
+        arrays: ArrayMatching,
     },
+    JSONPath {
+        predicates: Vec<JsonPathPredicate>,
+    },
+    XML {
+        #[serde(with = "serde_mock::parsed_string")]
+        xml: XmlDocument,
+    },
+    XPath(XPathBody),
     TEXT {
-        text: String,
+        text: StringMatcher,
     },
 }
 
+impl RequestBody {
+    pub fn matches(&self, body: &str) -> bool {
+        match self {
+            RequestBody::JSON { json } => serde_json::from_str(body)
+                .map(|body: Value| *json == body)
+                .unwrap_or(false),
+            RequestBody::JSONSubset { json, arrays } => serde_json::from_str(body)
+                .map(|body: Value| json::is_subset(json, &body, *arrays))
+                .unwrap_or(false),
+            RequestBody::JSONPath { predicates } => serde_json::from_str(body)
+                .map(|body: Value| predicates.iter().all(|predicate| predicate.test(&body)))
+                .unwrap_or(false),
+            RequestBody::XML { xml } => xml.matches(body),
+            RequestBody::XPath(xpath) => xpath.matches(body),
+            RequestBody::TEXT { text } => text.matches(body),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum StringMatcher {
+    Exact(String),
+    Regex(#[serde(with = "serde_mock::parsed_string")] Pattern),
+    Contains(String),
+    StartsWith(String),
+    EndsWith(String),
+    Any,
+}
+
+impl StringMatcher {
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            StringMatcher::Exact(exact) => exact == value,
+            StringMatcher::Regex(pattern) => pattern.regex.is_match(value),
+            StringMatcher::Contains(part) => value.contains(part.as_str()),
+            StringMatcher::StartsWith(prefix) => value.starts_with(prefix.as_str()),
+            StringMatcher::EndsWith(suffix) => value.ends_with(suffix.as_str()),
+            StringMatcher::Any => true,
+        }
+    }
+}
+
+/// Regular expression compiled at configuration load, the whole value must match it.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Ok(Pattern {
+            source: source.to_string(),
+            regex: Regex::new(&format!("^(?:{})$", source))?,
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum RequestPath {
+    Exact(String),
+    Template(#[serde(with = "serde_mock::parsed_string")] PathTemplate),
+    Prefix(String),
+    Matching(StringMatcher),
+}
+
+impl RequestPath {
+    pub fn matches(&self, path: &str) -> bool {
+        self.captures(path).is_some()
+    }
+
+    /// Named segments captured from `path`, `None` if the path doesn't match.
+    pub fn captures(&self, path: &str) -> Option<HashMap<String, String>> {
+        match self {
+            RequestPath::Exact(exact) if exact == path => Some(HashMap::new()),
+            RequestPath::Exact(_) => None,
+            RequestPath::Template(template) => template.captures(path),
+            RequestPath::Prefix(prefix) => {
+                let matching = path == prefix
+                    || (path.starts_with(prefix.as_str())
+                        && (prefix.ends_with('/') || path[prefix.len()..].starts_with('/')));
+                if matching {
+                    Some(HashMap::new())
+                } else {
+                    None
+                }
+            }
+            RequestPath::Matching(matcher) if matcher.matches(path) => Some(HashMap::new()),
+            RequestPath::Matching(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum PathSegment {
+    Literal(String),
+    Capture(String),
+}
+
+/// Path with named segments, ex: `/users/{id}/orders/{orderId}`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PathTemplate {
+    template: String,
+    segments: Vec<PathSegment>,
+}
+
+impl PathTemplate {
+    pub fn captures(&self, path: &str) -> Option<HashMap<String, String>> {
+        let parts: Vec<&str> = path.split('/').collect();
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+        let mut captures = HashMap::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            match segment {
+                PathSegment::Literal(literal) if literal == part => continue,
+                PathSegment::Capture(name) if !part.is_empty() => {
+                    captures.insert(name.clone(), part.to_string());
+                }
+                _ => return None,
+            }
+        }
+        Some(captures)
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let segments = template
+            .split('/')
+            .map(PathSegment::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid path template {} : {}", template, e))?;
+        Ok(PathTemplate {
+            template: template.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl PathSegment {
+    fn parse(segment: &str) -> Result<PathSegment, String> {
+        let is_capture = segment.len() > 2 && segment.starts_with('{') && segment.ends_with('}');
+        let name = if is_capture {
+            &segment[1..segment.len() - 1]
+        } else {
+            segment
+        };
+        if name.contains(|c| c == '{' || c == '}') {
+            return Err(format!(
+                "segment {} should be a text or a whole capture like {{name}}",
+                segment
+            ));
+        }
+        if is_capture {
+            Ok(PathSegment::Capture(name.to_string()))
+        } else {
+            Ok(PathSegment::Literal(segment.to_string()))
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct HttpRequest {
     pub method: Option<HttpMethod>,
-    pub path: Option<String>,
+    pub path: Option<RequestPath>,
     pub body: Option<RequestBody>,
-    pub params: Vec<(String, String)>,
-    pub headers: HashMap<String, String>,
+    pub params: Vec<(String, StringMatcher)>,
+    #[serde(with = "serde_mock::entries")]
+    pub headers: Vec<(String, StringMatcher)>,
+    #[serde(default, with = "serde_mock::matcher_nodes")]
+    pub matcher: Option<RequestMatcher>,
+}
+
+/// Upstream server answering the requests instead of the expectation.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Proxy {
+    #[serde(with = "serde_mock::parsed_string")]
+    pub target: ProxyTarget,
+    /// Headers replacing the request headers with the same name
+    #[serde(default, with = "serde_mock::headers")]
+    pub headers: Headers,
+}
+
+/// Absolute http or https url of an upstream server, ex: `http://users-service:8080/api`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProxyTarget(Uri);
+
+impl ProxyTarget {
+    /// Url of the upstream resource for the `request` path and query.
+    pub fn upstream_uri(&self, request: &Uri) -> Result<Uri, String> {
+        let path = request.path_and_query().map_or("/", |p| p.as_str());
+        let uri = format!("{}{}", self.0.to_string().trim_end_matches('/'), path);
+        uri.parse()
+            .map_err(|e| format!("{} isn't a valid upstream url : {}", uri, e))
+    }
+}
+
+impl FromStr for ProxyTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let uri: Uri = target
+            .parse()
+            .map_err(|e| format!("{} isn't a valid proxy url : {}", target, e))?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") if uri.host().is_some() => Ok(ProxyTarget(uri)),
+            _ => Err(format!(
+                "{} isn't an absolute http or https proxy url",
+                target
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ProxyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Failure of the connection sent instead of the response.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Fault {
+    /// Reset the connection without sending anything
+    ConnectionReset,
+    /// Send the head and half of the body then reset the connection
+    TruncatedBody,
+    /// Send bytes that aren't an http response then close the connection
+    MalformedResponse,
+    /// Close the connection without sending anything
+    EmptyResponse,
+    /// Never answer, the connection stays open
+    Stall,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -57,13 +385,51 @@ pub struct HttpResponse {
     #[serde(rename = "statusReason")]
     pub status_reason: Option<String>,
     pub body: Option<String>,
-    pub headers: HashMap<String, String>,
+    #[serde(with = "serde_mock::headers")]
+    pub headers: Headers,
+    /// JSON body, used instead of `body`
+    #[serde(rename = "bodyJSON", default, with = "serde_mock::json_value::option")]
+    pub body_json: Option<Value>,
+    /// Body rendered from the request, used instead of `body` and `bodyJSON`
+    #[serde(rename = "bodyTemplate", default)]
+    pub body_template: Option<Template>,
+    /// Headers with values rendered from the request, sent after `headers`
+    #[serde(rename = "headerTemplates", default, with = "serde_mock::entries")]
+    pub header_templates: Vec<(String, Template)>,
+    /// Time waited before sending the response
+    #[serde(default)]
+    pub delay: Option<Delay>,
+    /// Connection failure sent instead of the response
+    #[serde(default)]
+    pub fault: Option<Fault>,
+    /// Upstream answering instead of the response, `headers` are added to its response
+    #[serde(default)]
+    pub proxy: Option<Proxy>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct Expectation {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Scenario state required to match and new state once answered
+    #[serde(default)]
+    pub scenario: Option<ScenarioStep>,
+    /// Number of requests served before the expectation stops matching, unlimited when empty
+    #[serde(default)]
+    pub times: Option<u64>,
+    /// Milliseconds after the load time before the expectation starts matching
+    #[serde(rename = "activeAfter", default)]
+    pub active_after: Option<u64>,
+    /// Milliseconds after the load time when the expectation stops matching and is removed
+    #[serde(rename = "expiresAfter", default)]
+    pub expires_after: Option<u64>,
     pub request: HttpRequest,
     pub response: HttpResponse,
+    /// Responses used instead of `response`, one for each request
+    #[serde(default)]
+    pub responses: Option<ResponseSequence>,
 }
 
 pub struct AndPredicate<'a> {
@@ -72,26 +438,23 @@ pub struct AndPredicate<'a> {
 }
 
 impl<'a> Predicate for AndPredicate<'a> {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool {
-        if self.pred1.test(exp, req) {
-            return self.pred2.test(exp, req);
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        if self.pred1.test(expected, req) {
+            return self.pred2.test(expected, req);
         }
         false
     }
 }
 
 pub fn and<'a>(pred1: &'a dyn Predicate, pred2: &'a dyn Predicate) -> AndPredicate<'a> {
-    AndPredicate {
-        pred1: pred1,
-        pred2: pred2,
-    }
+    AndPredicate { pred1, pred2 }
 }
 
 pub struct MatchMethod;
 
 impl Predicate for MatchMethod {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool {
-        exp.request
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        expected
             .method
             .as_ref()
             .map(|m| m == &req.method)
@@ -102,11 +465,11 @@ impl Predicate for MatchMethod {
 pub struct MatchPath;
 
 impl Predicate for MatchPath {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool {
-        exp.request
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        expected
             .path
             .as_ref()
-            .map(|p| p == &req.path)
+            .map(|p| p.matches(&req.path))
             .unwrap_or(true)
     }
 }
@@ -114,73 +477,277 @@ impl Predicate for MatchPath {
 pub struct MatchBody;
 
 impl Predicate for MatchBody {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool {
-        match &exp.request.body {
-            Some(RequestBody::JSON { json }) => serde_json::from_str(req.body.as_ref())
-                .map(|body: Value| *json == body)
-                .unwrap_or(false),
-            Some(RequestBody::TEXT { text }) => *text == req.body,
-            _ => true,
-        }
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        expected
+            .body
+            .as_ref()
+            .map(|b| b.matches(&req.body))
+            .unwrap_or(true)
     }
 }
 
 struct MatchParams;
 
 impl Predicate for MatchParams {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool {
-        for (k, v) in exp.request.params.iter() {
-            match req.params.get(k) {
-                Some(set) if set.contains(v) => continue,
-                _ => return false,
-            }
-        }
-        true
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        expected
+            .params
+            .iter()
+            .all(|(k, matcher)| req.has_param(k, matcher))
     }
 }
 
 struct MatchHeaders;
 
 impl Predicate for MatchHeaders {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool {
-        for (k, v) in exp.request.headers.iter() {
-            match req.headers.get(k) {
-                Some(vv) if v == vv => continue,
-                _ => return false,
-            }
-        }
-        true
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        expected
+            .headers
+            .iter()
+            .all(|(name, matcher)| req.has_header(name, matcher))
+    }
+}
+
+struct MatchCriteria;
+
+impl Predicate for MatchCriteria {
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool {
+        expected
+            .matcher
+            .as_ref()
+            .map(|m| m.test(expected, req))
+            .unwrap_or(true)
     }
 }
 
 pub trait Predicate {
-    fn test(&self, exp: &Expectation, req: &IncomingRequest) -> bool;
+    fn test(&self, expected: &HttpRequest, req: &IncomingRequest) -> bool;
 }
 
-impl Expectation {
+/// Criterion of an expected request refused by an incoming request.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub enum Mismatch {
+    Method,
+    Path,
+    Param(String),
+    Header(String),
+    Body,
+    Matcher,
+    /// The scenario isn't in the required state
+    Scenario(String),
+}
+
+impl HttpRequest {
     pub fn test(&self, req: &IncomingRequest) -> bool {
         and(
             &MatchMethod,
             &and(
                 &MatchPath,
-                &and(&MatchBody, &and(&MatchParams, &MatchHeaders)),
+                &and(
+                    &MatchBody,
+                    &and(&MatchParams, &and(&MatchHeaders, &MatchCriteria)),
+                ),
             ),
         )
         .test(self, req)
     }
 
-    pub fn look_for_expectation<'a, 'b>(
-        expectations: &'a Vec<Expectation>,
+    /// All the criteria refused by `req`, empty if `req` matches this request.
+    pub fn mismatches(&self, req: &IncomingRequest) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+        if !MatchMethod.test(self, req) {
+            mismatches.push(Mismatch::Method);
+        }
+        if !MatchPath.test(self, req) {
+            mismatches.push(Mismatch::Path);
+        }
+        for (key, matcher) in self.params.iter() {
+            if !req.has_param(key, matcher) {
+                mismatches.push(Mismatch::Param(key.clone()));
+            }
+        }
+        for (name, matcher) in self.headers.iter() {
+            if !req.has_header(name, matcher) {
+                mismatches.push(Mismatch::Header(name.clone()));
+            }
+        }
+        if !MatchBody.test(self, req) {
+            mismatches.push(Mismatch::Body);
+        }
+        if !MatchCriteria.test(self, req) {
+            mismatches.push(Mismatch::Matcher);
+        }
+        mismatches
+    }
+}
+
+/// Expectation stored in the server state, with its server assigned id and where it was loaded from.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ExpectationEntry {
+    pub id: u64,
+    pub source: String,
+    #[serde(rename = "loadedAt", with = "serde_mock::rfc3339")]
+    pub loaded_at: SystemTime,
+    /// Requests left to serve when the expectation has limited `times`
+    pub remaining: Option<Counter>,
+    /// Number of requests answered by the expectation
+    pub served: Counter,
+    #[serde(flatten)]
+    pub expectation: Expectation,
+}
+
+/// Request count of an expectation entry, updated by the requests matched concurrently.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn new(count: u64) -> Self {
+        Counter(AtomicU64::new(count))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Copy of the count when the entry is cloned.
+impl Clone for Counter {
+    fn clone(&self) -> Self {
+        Counter::new(self.get())
+    }
+}
+
+impl PartialEq for Counter {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl ExpectationEntry {
+    /// Response to the last request counted in `served`.
+    pub fn response(&self) -> &HttpResponse {
+        match self.expectation.responses.as_ref() {
+            Some(sequence) => sequence.response(self.served.get().saturating_sub(1)),
+            None => &self.expectation.response,
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining.as_ref().map_or(false, |r| r.get() == 0)
+    }
+
+    /// Count a request answered by the expectation, `None` when it's exhausted.
+    /// Returns the entry as seen by this request : `served` counts it, whatever the requests
+    /// counted concurrently.
+    pub fn claim(&self) -> Option<ExpectationEntry> {
+        if let Some(remaining) = self.remaining.as_ref() {
+            remaining
+                .0
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| r.checked_sub(1))
+                .ok()?;
+        }
+        let served = self.served.0.fetch_add(1, Ordering::SeqCst) + 1;
+        Some(ExpectationEntry {
+            served: Counter::new(served),
+            ..self.clone()
+        })
+    }
+
+    /// Is `now` inside the activation window of the expectation.
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        let activation = self.loaded_at
+            + Duration::from_millis(self.expectation.active_after.unwrap_or_default());
+        now >= activation && !self.is_expired(now)
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expectation.expires_after.map_or(false, |ttl| {
+            now >= self.loaded_at + Duration::from_millis(ttl)
+        })
+    }
+}
+
+impl AsRef<Expectation> for Expectation {
+    fn as_ref(&self) -> &Expectation {
+        self
+    }
+}
+
+impl AsRef<Expectation> for ExpectationEntry {
+    fn as_ref(&self) -> &Expectation {
+        &self.expectation
+    }
+}
+
+/// Expectation refusing a request, with the refused criteria.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ExpectationMiss<E> {
+    pub expectation: E,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Expectation {
+    pub fn test(&self, req: &IncomingRequest) -> bool {
+        self.request.test(req)
+    }
+
+    fn mismatches(&self, req: &IncomingRequest, scenarios: &Scenarios) -> Vec<Mismatch> {
+        let mut mismatches = self.request.mismatches(req);
+        if !scenarios.accept(self) {
+            if let Some(step) = self.scenario.as_ref() {
+                mismatches.push(Mismatch::Scenario(step.name.clone()));
+            }
+        }
+        mismatches
+    }
+
+    /// The `count` expectations with the fewest criteria refused by `req`, in configuration order on ties.
+    pub fn closest_expectations<E: AsRef<Expectation> + Clone>(
+        expectations: &[E],
+        req: &IncomingRequest,
+        scenarios: &Scenarios,
+        count: usize,
+    ) -> Vec<ExpectationMiss<E>> {
+        let mut misses: Vec<(usize, Vec<Mismatch>)> = expectations
+            .iter()
+            .map(|e| e.as_ref().mismatches(req, scenarios))
+            .enumerate()
+            .collect();
+        misses.sort_by_key(|(_, mismatches)| mismatches.len());
+        misses
+            .into_iter()
+            .take(count)
+            .map(|(index, mismatches)| ExpectationMiss {
+                expectation: expectations[index].clone(),
+                mismatches,
+            })
+            .collect()
+    }
+
+    /// Segments captured by the path template of this expectation for `req`.
+    pub fn path_captures(&self, req: &IncomingRequest) -> HashMap<String, String> {
+        self.request
+            .path
+            .as_ref()
+            .and_then(|p| p.captures(&req.path))
+            .unwrap_or_default()
+    }
+
+    pub fn look_for_expectation<'a, 'b, E: AsRef<Expectation>>(
+        expectations: &'a [E],
         req: &'b IncomingRequest,
-    ) -> Option<&'a Expectation> {
-        expectations.iter().find(|e| e.test(req))
+    ) -> Option<&'a E> {
+        expectations.iter().find(|e| e.as_ref().test(req))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::mock::json::JsonPathMatcher;
     use crate::mock::model::{HttpRequest, HttpResponse};
+    use crate::mock::xml::{XPathMatcher, XPathPredicate};
     use serde_json::json;
 
     #[test]
@@ -244,12 +811,12 @@ mod test {
     fn test_deserialize_request_textual_body() {
         assert_eq!(
             RequestBody::TEXT {
-                text: String::from("carpe diem.")
+                text: StringMatcher::Exact(String::from("carpe diem."))
             },
             serde_dhall::from_str(
                 r###"
             let Mock = ./dhall/Mock/package.dhall
-            in Mock.Body.TEXT { text = "carpe diem." }
+            in Mock.Body.TEXT { text = Mock.StringMatcher.Exact "carpe diem." }
         "###
             )
             .parse()
@@ -275,101 +842,280 @@ mod test {
     }
 
     #[test]
-    fn test_deserialize_http_params() {
-        let data = r###"
-            let Mock = ./dhall/Mock/package.dhall
-            in Mock.HttpRequest::{ params = [ { key = "foo", value = "bar"}]
-                                 }
-        "###;
-
+    fn test_deserialize_request_json_subset_body() {
         assert_eq!(
-            HttpRequest {
-                method: None,
-                path: None,
-                body: None,
-                headers: HashMap::new(),
-                params: vec![(String::from("foo"), String::from("bar"))],
+            RequestBody::JSONSubset {
+                json: json!({ "tags": [ "carpe diem." ] }),
+                arrays: ArrayMatching::Contains
             },
-            serde_dhall::from_str(data).parse().unwrap()
+            serde_dhall::from_str(
+                r###"
+                    let Mock = ./dhall/Mock/package.dhall
+                    in Mock.Body.JSONSubset { json = "{ \"tags\": [ \"carpe diem.\" ] }"
+                                            , arrays = Mock.ArrayMatching.Contains
+                                            }
+                "###
+            )
+            .parse()
+            .unwrap()
         );
     }
 
-    #[test]
-    fn test_deserialize_http_headers() {
-        let data = r###"
-            let Mock = ./dhall/Mock/package.dhall
-            in Mock.HttpRequest::{ headers = [ Mock.contentTypeJSON ]
-                                 }
-        "###;
-
-        let mut headers = HashMap::new();
-        headers.insert(
-            String::from("Content-Type"),
-            String::from("application/json"),
-        );
+    /// JSON value written as the Prelude `JSON` functions would build it, the tests can't
+    /// download the Prelude.
+    const DHALL_JSON: &str = r###"
+        let Mock = ./dhall/Mock/package.dhall
+        let Constructors = \(JSON : Type) -> { array : List JSON -> JSON, bool : Bool -> JSON, double : Double -> JSON, integer : Integer -> JSON, null : JSON, object : List { mapKey : Text, mapValue : JSON } -> JSON, string : Text -> JSON }
+        let user : Mock.JSON
+            = \(JSON : Type) -> \(json : Constructors JSON)
+           -> json.object [ { mapKey = "name", mapValue = json.string "robert" }
+                          , { mapKey = "age", mapValue = json.integer +42 }
+                          , { mapKey = "score", mapValue = json.double 1.5 }
+                          , { mapKey = "tags", mapValue = json.array [ json.string "admin", json.bool True, json.null ] }
+                          ]
+    "###;
 
+    #[test]
+    fn test_deserialize_request_json_value_body() {
+        let expected =
+            json!({ "name": "robert", "age": 42, "score": 1.5, "tags": [ "admin", true, null ] });
         assert_eq!(
-            HttpRequest {
-                method: None,
-                path: None,
-                body: None,
-                params: vec![],
-                headers: headers
+            RequestBody::JSON {
+                json: expected.clone()
             },
-            serde_dhall::from_str(data).parse().unwrap()
+            serde_dhall::from_str(&format!(
+                "{} in Mock.Body.JSONValue {{ json = Mock.jsonNodes user }}",
+                DHALL_JSON
+            ))
+            .parse()
+            .unwrap()
         );
-    }
-
-    #[test]
-    fn test_deserialize_http_request() {
-        let data = r###"
-            let Mock = ./dhall/Mock/package.dhall
-            in Mock.HttpRequest::{ method = Some Mock.HttpMethod.GET
-                                 , path = Some "/path"
-                                 }
-        "###;
         assert_eq!(
-            HttpRequest {
-                method: Some(HttpMethod::GET),
-                path: Some("/path".to_string()),
-                body: None,
-                params: vec![],
-                headers: HashMap::new()
+            RequestBody::JSONSubset {
+                json: expected,
+                arrays: ArrayMatching::Contains
             },
-            serde_dhall::from_str(data).parse().unwrap()
+            serde_dhall::from_str(&format!(
+                "{} in Mock.Body.JSONSubsetValue {{ json = Mock.jsonNodes user, arrays = Mock.ArrayMatching.Contains }}",
+                DHALL_JSON
+            ))
+            .parse()
+            .unwrap()
         );
-
-        let data = r###"
-            let Mock = ./dhall/Mock/package.dhall
-            in Mock.HttpRequest::{ method = None Mock.HttpMethod
-                                 }
-        "###;
         assert_eq!(
-            HttpRequest {
-                method: None,
-                path: None,
-                body: None,
-                params: vec![],
-                headers: HashMap::new()
+            RequestBody::JSONSubset {
+                json: json!({ "name": "robert", "age": 42, "score": 1.5, "tags": [ "admin", true, null ] }),
+                arrays: ArrayMatching::Contains
             },
-            serde_dhall::from_str(data).parse().unwrap()
+            serde_dhall::from_str(&format!(
+                "{} in Mock.jsonSubsetBody Mock.ArrayMatching.Contains user",
+                DHALL_JSON
+            ))
+            .parse()
+            .unwrap()
+        );
+        assert_eq!(
+            serde_dhall::from_str(&format!(
+                "{} in Mock.Body.JSONValue {{ json = Mock.jsonNodes user }}",
+                DHALL_JSON
+            ))
+            .parse::<RequestBody>()
+            .unwrap(),
+            serde_dhall::from_str(&format!("{} in Mock.jsonBody user", DHALL_JSON))
+                .parse()
+                .unwrap()
         );
     }
 
     #[test]
-    fn test_deserialize_http_request_fail() {
-        let data = r###"
-            let Mock = ./dhall/Mock/package.dhall
-            in { method = Mock.HttpMethod.GET
-                                 , path = "/path"
-                                 }
-        "###;
-        assert!(serde_dhall::from_str(data).parse::<HttpRequest>().is_err());
+    fn test_deserialize_http_response_json_body() {
+        let response: HttpResponse = serde_dhall::from_str(&format!(
+            "{} in Mock.HttpResponse::{{ bodyJSON = Mock.jsonResponseBody user }}",
+            DHALL_JSON
+        ))
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            Some(
+                json!({ "name": "robert", "age": 42, "score": 1.5, "tags": [ "admin", true, null ] })
+            ),
+            response.body_json
+        );
+    }
+
+    #[test]
+    fn test_deserialize_request_json_path_body() {
+        assert_eq!(
+            RequestBody::JSONPath {
+                predicates: vec![JsonPathPredicate {
+                    path: "$.amount".parse().unwrap(),
+                    matcher: JsonPathMatcher::GreaterThan(100.0)
+                }]
+            },
+            serde_dhall::from_str(
+                r###"
+                    let Mock = ./dhall/Mock/package.dhall
+                    in Mock.Body.JSONPath { predicates = [ { path = "$.amount", matcher = Mock.JSONPathMatcher.GreaterThan 100.0 } ] }
+                "###
+            )
+            .parse()
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_request_xpath_body() {
+        assert_eq!(
+            RequestBody::XPath(XPathBody {
+                namespaces: vec![(String::from("u"), String::from("urn:users"))],
+                predicates: vec![XPathPredicate {
+                    path: "//u:GetUser/@id".parse().unwrap(),
+                    matcher: XPathMatcher::Matches(StringMatcher::Exact(String::from("42")))
+                }]
+            }),
+            serde_dhall::from_str(
+                r###"
+                    let Mock = ./dhall/Mock/package.dhall
+                    in Mock.Body.XPath { namespaces = [ { prefix = "u", uri = "urn:users" } ]
+                                       , predicates = [ { path = "//u:GetUser/@id", matcher = Mock.XPathMatcher.Matches (Mock.StringMatcher.Exact "42") } ]
+                                       }
+                "###
+            )
+            .parse()
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_request_xml_body_fail_on_invalid_document() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Body.XML { xml = "<user><name>robert</user>" }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<RequestBody>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_request_xpath_body_fail_on_undeclared_prefix() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Body.XPath { namespaces = [ { prefix = "soap", uri = "http://schemas.xmlsoap.org/soap/envelope/" } ]
+                               , predicates = [ { path = "//u:GetUser", matcher = Mock.XPathMatcher.Exists } ]
+                               }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<RequestBody>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_request_json_path_body_fail_on_invalid_path() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Body.JSONPath { predicates = [ { path = "$.amount[", matcher = Mock.JSONPathMatcher.Exists } ] }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<RequestBody>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_http_params() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpRequest::{ params = [ { key = "foo", value = Mock.StringMatcher.Exact "bar" } ]
+                                 }
+        "###;
+
+        assert_eq!(
+            HttpRequest {
+                method: None,
+                path: None,
+                body: None,
+                headers: vec![],
+                params: vec![(
+                    String::from("foo"),
+                    StringMatcher::Exact(String::from("bar"))
+                )],
+                matcher: None,
+            },
+            serde_dhall::from_str(data).parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_http_headers() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpRequest::{ headers = [ Mock.matchHeader Mock.contentTypeJSON ]
+                                 }
+        "###;
+
+        assert_eq!(
+            HttpRequest {
+                method: None,
+                path: None,
+                body: None,
+                params: vec![],
+                headers: vec![(
+                    String::from("Content-Type"),
+                    StringMatcher::Exact(String::from("application/json")),
+                )],
+                matcher: None,
+            },
+            serde_dhall::from_str(data).parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_http_request() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpRequest::{ method = Some Mock.HttpMethod.GET
+                                 , path = Some (Mock.Path.Exact "/path")
+                                 }
+        "###;
+        assert_eq!(
+            HttpRequest {
+                method: Some(HttpMethod::GET),
+                path: Some(RequestPath::Exact("/path".to_string())),
+                body: None,
+                params: vec![],
+                headers: vec![],
+                matcher: None
+            },
+            serde_dhall::from_str(data).parse().unwrap()
+        );
+
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpRequest::{ method = None Mock.HttpMethod
+                                 }
+        "###;
+        assert_eq!(
+            HttpRequest {
+                method: None,
+                path: None,
+                body: None,
+                params: vec![],
+                headers: vec![],
+                matcher: None
+            },
+            serde_dhall::from_str(data).parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_http_request_fail() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in { method = Mock.HttpMethod.GET
+                                 , path = "/path"
+                                 }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<HttpRequest>().is_err());
 
         let data = r###"
             let Mock = ./dhall/Mock/package.dhall
             in { method = Mock.HttpMethod.GET
-               , path = Some "/path"
+               , path = Some (Mock.Path.Exact "/path")
                }
         "###;
         assert!(serde_dhall::from_str(data).parse::<HttpRequest>().is_err());
@@ -396,7 +1142,13 @@ mod test {
                 status_code: Some(200),
                 status_reason: None,
                 body: Some("Hello, world !".to_string()),
-                headers: HashMap::new(),
+                headers: vec![],
+                body_json: None,
+                body_template: None,
+                header_templates: vec![],
+                delay: None,
+                fault: None,
+                proxy: None,
             },
             serde_dhall::from_str(data).parse().unwrap()
         );
@@ -411,7 +1163,13 @@ mod test {
                 status_code: Some(200),
                 status_reason: Some("Everything went fine".to_string()),
                 body: None,
-                headers: HashMap::new(),
+                headers: vec![],
+                body_json: None,
+                body_template: None,
+                header_templates: vec![],
+                delay: None,
+                fault: None,
+                proxy: None,
             },
             serde_dhall::from_str(data).parse().unwrap()
         );
@@ -447,136 +1205,714 @@ mod test {
     }
 
     #[test]
-    fn test_deserialize_expectation() {
+    fn test_deserialize_http_response_headers_map() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpResponse::{ headers = toMap { Location = "/users/42" }
+                                            # Mock.repeatedHeader "Set-Cookie" [ "session=42", "theme=dark" ]
+                                            # Mock.repeatedHeader "X-Empty" ([] : List Text)
+                                  }
+        "###;
+        let response: HttpResponse = serde_dhall::from_str(data).parse().unwrap();
+        assert_eq!(
+            vec![
+                (String::from("Location"), String::from("/users/42")),
+                (String::from("Set-Cookie"), String::from("session=42")),
+                (String::from("Set-Cookie"), String::from("theme=dark")),
+            ],
+            response.headers
+        );
+    }
+
+    #[test]
+    fn test_deserialize_http_response_templates() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpResponse::{ bodyTemplate    = Some "{ \"id\": \"{{path.id}}\" }"
+                                  , headerTemplates = [ { mapKey = "X-Request-Id", mapValue = "{{header.X-Request-Id}}" } ]
+                                  }
+        "###;
+        let response: HttpResponse = serde_dhall::from_str(data).parse().unwrap();
+        assert_eq!(
+            Some(r#"{ "id": "{{path.id}}" }"#.parse().unwrap()),
+            response.body_template
+        );
+        assert_eq!(
+            vec![(
+                String::from("X-Request-Id"),
+                "{{header.X-Request-Id}}".parse().unwrap()
+            )],
+            response.header_templates
+        );
+
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpResponse::{ bodyTemplate = Some "{{ cookie.session }}" }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<HttpResponse>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_http_response_delay() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in [ Mock.HttpResponse::{ delay = Some (Mock.Delay.Fixed 200) }
+               , Mock.HttpResponse::{ delay = Some (Mock.Delay.Uniform { min = 100, max = 300 }) }
+               , Mock.HttpResponse::{ delay = Some (Mock.Delay.LogNormal { median = 50, p99 = 2000 }) }
+               ]
+        "###;
+        let responses: Vec<HttpResponse> = serde_dhall::from_str(data).parse().unwrap();
+        assert_eq!(
+            vec![
+                Some(Delay::Fixed(200)),
+                Some(Delay::Uniform { min: 100, max: 300 }),
+                Some(Delay::LogNormal {
+                    median: 50,
+                    p99: 2000
+                })
+            ],
+            responses
+                .into_iter()
+                .map(|r| r.delay)
+                .collect::<Vec<Option<Delay>>>()
+        );
+
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpResponse::{ delay = Some (Mock.Delay.Uniform { min = 300, max = 100 }) }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<HttpResponse>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_http_response_proxy() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.HttpResponse::{ proxy = Some Mock.Proxy::{ target = "http://upstream:8080/api/", headers = [ { mapKey = "Authorization", mapValue = "Bearer mock" } ] } }
+        "###;
+        let response: HttpResponse = serde_dhall::from_str(data).parse().unwrap();
+        let proxy = response.proxy.unwrap();
+        assert_eq!(
+            "http://upstream:8080/api/users/42?page=2",
+            proxy
+                .target
+                .upstream_uri(&"/users/42?page=2".parse().unwrap())
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            vec![(String::from("Authorization"), String::from("Bearer mock"))],
+            proxy.headers
+        );
+
+        assert!("/api".parse::<ProxyTarget>().is_err());
+        assert!("ftp://upstream/api".parse::<ProxyTarget>().is_err());
+        assert!("https://upstream".parse::<ProxyTarget>().is_ok());
+    }
+
+    #[test]
+    fn test_deserialize_expectation() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
+                                              , path    = Some (Mock.Path.Exact "/greet/pwet")
+                                              }
+            , response = Mock.HttpResponse::{ statusCode   = Mock.statusOK
+                                            , body         = Some "Hello, pwet !"
+                                            }
+            }
+        "###;
+        let expected = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: HttpRequest {
+                method: Some(HttpMethod::GET),
+                path: Some(RequestPath::Exact("/greet/pwet".to_string())),
+                body: None,
+                params: vec![],
+                headers: vec![],
+                matcher: None,
+            },
+            response: HttpResponse {
+                status_code: Some(200),
+                status_reason: None,
+                body: Some("Hello, pwet !".to_string()),
+                headers: vec![],
+                body_json: None,
+                body_template: None,
+                header_templates: vec![],
+                delay: None,
+                fault: None,
+                proxy: None,
+            },
+        };
+        assert_eq!(expected, serde_dhall::from_str(data).parse().unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_expectation_name_and_tags() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Expectation::{ name     = Some "greet pwet"
+                                 , tags     = [ "greet", "smoke" ]
+                                 , request  = Mock.HttpRequest::{=}
+                                 , response = Mock.HttpResponse::{=}
+                                 }
+        "###;
+        let expectation: Expectation = serde_dhall::from_str(data).parse().unwrap();
+        assert_eq!(Some(String::from("greet pwet")), expectation.name);
+        assert_eq!(
+            vec![String::from("greet"), String::from("smoke")],
+            expectation.tags
+        );
+    }
+
+    #[test]
+    fn test_deserialize_expectation_times() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in [ Mock.Expectation::{ times = Some 3, request = Mock.HttpRequest::{=}, response = Mock.HttpResponse::{=} }
+               , Mock.Expectation::{ request = Mock.HttpRequest::{=}, response = Mock.HttpResponse::{=} }
+               ]
+        "###;
+        let expectations: Vec<Expectation> = serde_dhall::from_str(data).parse().unwrap();
+        assert_eq!(Some(3), expectations[0].times);
+        assert_eq!(None, expectations[1].times);
+    }
+
+    #[test]
+    fn test_expectation_activation_window() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Expectation::{ activeAfter = Some 1000, expiresAfter = Some 5000, request = Mock.HttpRequest::{=}, response = Mock.HttpResponse::{=} }
+        "###;
+        let loaded_at = SystemTime::now();
+        let entry = ExpectationEntry {
+            id: 1,
+            source: String::from("test"),
+            loaded_at,
+            remaining: None,
+            served: Counter::default(),
+            expectation: serde_dhall::from_str(data).parse().unwrap(),
+        };
+        let at = |millis: u64| loaded_at + Duration::from_millis(millis);
+
+        assert!(!entry.is_active(at(500)));
+        assert!(entry.is_active(at(1000)));
+        assert!(entry.is_active(at(4999)));
+        assert!(!entry.is_expired(at(4999)));
+        assert!(!entry.is_active(at(5000)));
+        assert!(entry.is_expired(at(5000)));
+    }
+
+    #[test]
+    fn test_claim_limited_expectation_concurrently() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Expectation::{ times = Some 100, request = Mock.HttpRequest::{=} }
+        "###;
+        let entry = std::sync::Arc::new(ExpectationEntry {
+            id: 1,
+            source: String::from("test"),
+            loaded_at: SystemTime::now(),
+            remaining: Some(Counter::new(100)),
+            served: Counter::default(),
+            expectation: serde_dhall::from_str(data).parse().unwrap(),
+        });
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let entry = entry.clone();
+                std::thread::spawn(move || {
+                    (0..20)
+                        .filter_map(|_| entry.claim().map(|claimed| claimed.served.get()))
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+        let mut served: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        served.sort_unstable();
+
+        assert_eq!((1..=100).collect::<Vec<u64>>(), served);
+        assert!(entry.is_exhausted());
+        assert_eq!(100, entry.served.get());
+    }
+
+    #[test]
+    fn test_deserialize_expectation_fail() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in { request  = { }
+            , response = { }
+            }
+        "###;
+        assert!(serde_dhall::from_str(data).parse::<Expectation>().is_err());
+    }
+
+    #[test]
+    fn test_accept_matching_method() {
+        let req = HttpRequest {
+            method: Some(HttpMethod::GET),
+            path: None,
+            body: None,
+            params: vec![],
+            headers: vec![],
+            matcher: None,
+        };
+
+        let resp = HttpResponse {
+            status_code: Some(200),
+            status_reason: None,
+            body: None,
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
+        };
+
+        let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: req,
+            response: resp,
+        };
+
+        let income = IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from("/foo/bar"),
+            body: String::from(""),
+            params: HashMap::new(),
+            headers: HashMap::new(),
+            client_certificate: None,
+        };
+
+        let v = vec![exp.clone()];
+        let tested = Expectation::look_for_expectation(&v, &income);
+
+        assert_eq!(Some(&exp), tested);
+    }
+
+    #[test]
+    fn test_refuse_wrong_method() {
+        let req = HttpRequest {
+            method: Some(HttpMethod::POST),
+            path: None,
+            body: None,
+            params: vec![],
+            headers: vec![],
+            matcher: None,
+        };
+
+        let resp = HttpResponse {
+            status_code: Some(200),
+            status_reason: None,
+            body: None,
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
+        };
+
+        let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: req,
+            response: resp,
+        };
+
+        let income = IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from("/foo/bar"),
+            body: String::from(""),
+            params: HashMap::new(),
+            headers: HashMap::new(),
+            client_certificate: None,
+        };
+
+        let v = vec![exp];
+        let tested = Expectation::look_for_expectation(&v, &income);
+
+        assert_eq!(None, tested);
+    }
+
+    #[test]
+    fn test_accept_matching_path() {
+        let req = HttpRequest {
+            method: None,
+            path: Some(RequestPath::Exact(String::from("/foo/bar"))),
+            body: None,
+            params: vec![],
+            headers: vec![],
+            matcher: None,
+        };
+
+        let resp = HttpResponse {
+            status_code: Some(200),
+            status_reason: None,
+            body: None,
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
+        };
+
+        let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: req,
+            response: resp,
+        };
+
+        let income = IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from("/foo/bar"),
+            body: String::from(""),
+            params: HashMap::new(),
+            headers: HashMap::new(),
+            client_certificate: None,
+        };
+
+        let v = vec![exp.clone()];
+        let tested = Expectation::look_for_expectation(&v, &income);
+
+        assert_eq!(Some(&exp), tested);
+    }
+
+    #[test]
+    fn test_refuse_wrong_path() {
+        let req = HttpRequest {
+            method: None,
+            path: Some(RequestPath::Exact(String::from("/foo/bar"))),
+            body: None,
+            params: vec![],
+            headers: vec![],
+            matcher: None,
+        };
+
+        let resp = HttpResponse {
+            status_code: Some(200),
+            status_reason: None,
+            body: None,
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
+        };
+
+        let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: req,
+            response: resp,
+        };
+
+        let income = IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from("/users"),
+            body: String::from(""),
+            params: HashMap::new(),
+            headers: HashMap::new(),
+            client_certificate: None,
+        };
+
+        let v = vec![exp];
+        let tested = Expectation::look_for_expectation(&v, &income);
+
+        assert_eq!(None, tested);
+    }
+
+    #[test]
+    fn test_deserialize_request_path() {
+        assert_eq!(
+            RequestPath::Prefix(String::from("/api")),
+            serde_dhall::from_str(
+                r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Path.Prefix "/api"
+        "###
+            )
+            .parse()
+            .unwrap()
+        );
+        assert_eq!(
+            RequestPath::Template("/users/{id}".parse().unwrap()),
+            serde_dhall::from_str(
+                r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.Path.Template "/users/{id}"
+        "###
+            )
+            .parse()
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_request_path_fail_on_invalid_template() {
         let data = r###"
             let Mock = ./dhall/Mock/package.dhall
-            in { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
-                                              , path    = Some "/greet/pwet"
-                                              }
-            , response = Mock.HttpResponse::{ statusCode   = Mock.statusOK
-                                            , body         = Some "Hello, pwet !"
-                                            }
-            }
+            in Mock.Path.Template "/users/{id"
         "###;
-        let expected = Expectation {
-            request: HttpRequest {
-                method: Some(HttpMethod::GET),
-                path: Some("/greet/pwet".to_string()),
-                body: None,
-                params: vec![],
-                headers: HashMap::new(),
-            },
-            response: HttpResponse {
-                status_code: Some(200),
-                status_reason: None,
-                body: Some("Hello, pwet !".to_string()),
-                headers: HashMap::new(),
-            },
-        };
-        assert_eq!(expected, serde_dhall::from_str(data).parse().unwrap());
-    }
+        assert!(serde_dhall::from_str(data).parse::<RequestPath>().is_err());
 
-    #[test]
-    fn test_deserialize_expectation_fail() {
         let data = r###"
             let Mock = ./dhall/Mock/package.dhall
-            in { request  = { }
-            , response = { }
-            }
+            in Mock.Path.Template "/users/user-{id}"
         "###;
-        assert!(serde_dhall::from_str(data).parse::<Expectation>().is_err());
+        assert!(serde_dhall::from_str(data).parse::<RequestPath>().is_err());
     }
 
     #[test]
-    fn test_accept_matching_method() {
-        let req = HttpRequest {
-            method: Some(HttpMethod::GET),
-            path: None,
-            body: None,
-            params: vec![],
-            headers: HashMap::new(),
-        };
+    fn test_path_template_captures() {
+        let path = RequestPath::Template("/users/{id}/orders/{orderId}".parse().unwrap());
 
-        let resp = HttpResponse {
-            status_code: Some(200),
-            status_reason: None,
-            body: None,
-            headers: HashMap::new(),
-        };
+        let mut expected = HashMap::new();
+        expected.insert(String::from("id"), String::from("42"));
+        expected.insert(String::from("orderId"), String::from("A-1"));
+
+        assert_eq!(Some(expected), path.captures("/users/42/orders/A-1"));
+        assert_eq!(None, path.captures("/users/42/orders"));
+        assert_eq!(None, path.captures("/users//orders/A-1"));
+        assert_eq!(None, path.captures("/users/42/invoices/A-1"));
+    }
+
+    #[test]
+    fn test_path_prefix() {
+        let path = RequestPath::Prefix(String::from("/api"));
+
+        assert!(path.matches("/api"));
+        assert!(path.matches("/api/users"));
+        assert!(!path.matches("/apis"));
+        assert!(!path.matches("/v1/api"));
+    }
 
+    #[test]
+    fn test_accept_matching_path_template() {
         let exp = Expectation {
-            request: req,
-            response: resp,
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: HttpRequest {
+                method: None,
+                path: Some(RequestPath::Template("/users/{id}".parse().unwrap())),
+                body: None,
+                params: vec![],
+                headers: vec![],
+                matcher: None,
+            },
+            response: HttpResponse {
+                status_code: Some(200),
+                status_reason: None,
+                body: None,
+                headers: vec![],
+                body_json: None,
+                body_template: None,
+                header_templates: vec![],
+                delay: None,
+                fault: None,
+                proxy: None,
+            },
         };
 
         let income = IncomingRequest {
             method: HttpMethod::GET,
-            path: String::from("/foo/bar"),
+            path: String::from("/users/7e38a4e7"),
             body: String::from(""),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
         let tested = Expectation::look_for_expectation(&v, &income);
 
         assert_eq!(Some(&exp), tested);
+        assert_eq!(
+            Some(&String::from("7e38a4e7")),
+            exp.path_captures(&income).get("id")
+        );
     }
 
     #[test]
-    fn test_refuse_wrong_method() {
-        let req = HttpRequest {
-            method: Some(HttpMethod::POST),
-            path: None,
-            body: None,
-            params: vec![],
-            headers: HashMap::new(),
-        };
+    fn test_deserialize_string_matcher() {
+        assert_eq!(
+            StringMatcher::Regex("[0-9]+".parse().unwrap()),
+            serde_dhall::from_str(
+                r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.StringMatcher.Regex "[0-9]+"
+        "###
+            )
+            .parse()
+            .unwrap()
+        );
+        assert_eq!(
+            StringMatcher::Any,
+            serde_dhall::from_str(
+                r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.StringMatcher.Any
+        "###
+            )
+            .parse()
+            .unwrap()
+        );
+    }
 
-        let resp = HttpResponse {
-            status_code: Some(200),
-            status_reason: None,
-            body: None,
-            headers: HashMap::new(),
-        };
+    #[test]
+    fn test_deserialize_string_matcher_fail_on_invalid_regex() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.StringMatcher.Regex "[0-9+"
+        "###;
+        assert!(serde_dhall::from_str(data)
+            .parse::<StringMatcher>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_string_matcher() {
+        let regex = StringMatcher::Regex("[a-z]+-[0-9]+".parse().unwrap());
+        assert!(regex.matches("order-42"));
+        assert!(!regex.matches("order-42/items"));
+
+        assert!(StringMatcher::Exact(String::from("abc")).matches("abc"));
+        assert!(!StringMatcher::Exact(String::from("abc")).matches("abcd"));
+        assert!(StringMatcher::Contains(String::from("bc")).matches("abcd"));
+        assert!(StringMatcher::StartsWith(String::from("ab")).matches("abcd"));
+        assert!(!StringMatcher::StartsWith(String::from("cd")).matches("abcd"));
+        assert!(StringMatcher::EndsWith(String::from("cd")).matches("abcd"));
+        assert!(StringMatcher::Any.matches(""));
+    }
 
+    #[test]
+    fn test_accept_matching_path_regex() {
         let exp = Expectation {
-            request: req,
-            response: resp,
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: HttpRequest {
+                method: None,
+                path: Some(RequestPath::Matching(StringMatcher::Regex(
+                    "/(a|b)".parse().unwrap(),
+                ))),
+                body: None,
+                params: vec![],
+                headers: vec![],
+                matcher: None,
+            },
+            response: HttpResponse {
+                status_code: Some(200),
+                status_reason: None,
+                body: None,
+                headers: vec![],
+                body_json: None,
+                body_template: None,
+                header_templates: vec![],
+                delay: None,
+                fault: None,
+                proxy: None,
+            },
         };
 
-        let income = IncomingRequest {
+        let mut income = IncomingRequest {
             method: HttpMethod::GET,
-            path: String::from("/foo/bar"),
+            path: String::from("/b"),
             body: String::from(""),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
-        let tested = Expectation::look_for_expectation(&v, &income);
+        assert_eq!(Some(&exp), Expectation::look_for_expectation(&v, &income));
 
-        assert_eq!(None, tested);
+        income.path = String::from("/c");
+        assert_eq!(None, Expectation::look_for_expectation(&v, &income));
     }
 
     #[test]
-    fn test_accept_matching_path() {
+    fn test_accept_matching_json_body() {
+        let content = json!({ "maxime": "carpe diem." });
+
         let req = HttpRequest {
             method: None,
-            path: Some(String::from("/foo/bar")),
-            body: None,
+            path: None,
+            body: Some(RequestBody::JSON { json: content }),
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -584,9 +1920,10 @@ mod test {
         let income = IncomingRequest {
             method: HttpMethod::GET,
             path: String::from("/foo/bar"),
-            body: String::from(""),
+            body: String::from("{\n \"maxime\": \"carpe diem.\" \n}"),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
@@ -596,33 +1933,50 @@ mod test {
     }
 
     #[test]
-    fn test_refuse_wrong_path() {
+    fn test_refuse_wrong_json_body() {
+        let content = json!({ "maxime": "carpe diem." });
+
         let req = HttpRequest {
             method: None,
-            path: Some(String::from("/foo/bar")),
-            body: None,
+            path: None,
+            body: Some(RequestBody::JSON { json: content }),
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
 
         let income = IncomingRequest {
             method: HttpMethod::GET,
-            path: String::from("/users"),
-            body: String::from(""),
+            path: String::from("/foo/bar"),
+            body: String::from("{\n \"maxime\": \"this is not carpe diem.\" \n}"),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp];
@@ -632,63 +1986,96 @@ mod test {
     }
 
     #[test]
-    fn test_accept_matching_json_body() {
-        let content = json!({ "maxime": "carpe diem." });
-
+    fn test_accept_matching_json_subset_body() {
         let req = HttpRequest {
             method: None,
             path: None,
-            body: Some(RequestBody::JSON { json: content }),
+            body: Some(RequestBody::JSONSubset {
+                json: json!({ "maxime": "carpe diem." }),
+                arrays: ArrayMatching::Strict,
+            }),
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
 
-        let income = IncomingRequest {
-            method: HttpMethod::GET,
+        let mut income = IncomingRequest {
+            method: HttpMethod::POST,
             path: String::from("/foo/bar"),
-            body: String::from("{\n \"maxime\": \"carpe diem.\" \n}"),
+            body: String::from(
+                "{ \"maxime\": \"carpe diem.\", \"date\": \"2020-04-05T08:00:34\" }",
+            ),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
-        let tested = Expectation::look_for_expectation(&v, &income);
+        assert_eq!(Some(&exp), Expectation::look_for_expectation(&v, &income));
 
-        assert_eq!(Some(&exp), tested);
+        income.body = String::from("{ \"date\": \"2020-04-05T08:00:34\" }");
+        assert_eq!(None, Expectation::look_for_expectation(&v, &income));
     }
 
     #[test]
-    fn test_refuse_wrong_json_body() {
-        let content = json!({ "maxime": "carpe diem." });
-
+    fn test_accept_matching_text_body() {
         let req = HttpRequest {
             method: None,
             path: None,
-            body: Some(RequestBody::JSON { json: content }),
+            body: Some(RequestBody::TEXT {
+                text: StringMatcher::Exact(String::from("carpe diem.")),
+            }),
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -696,53 +2083,72 @@ mod test {
         let income = IncomingRequest {
             method: HttpMethod::GET,
             path: String::from("/foo/bar"),
-            body: String::from("{\n \"maxime\": \"this is not carpe diem.\" \n}"),
+            body: String::from("carpe diem."),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
         let tested = Expectation::look_for_expectation(&v, &income);
 
-        assert_eq!(None, tested);
+        assert_eq!(Some(&exp), tested);
     }
 
     #[test]
-    fn test_accept_matching_text_body() {
+    fn test_accept_matching_xml_body() {
         let req = HttpRequest {
             method: None,
             path: None,
-            body: Some(RequestBody::TEXT {
-                text: String::from("carpe diem."),
+            body: Some(RequestBody::XML {
+                xml: "<user id=\"42\"><name>robert</name></user>"
+                    .parse()
+                    .unwrap(),
             }),
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
 
-        let income = IncomingRequest {
-            method: HttpMethod::GET,
-            path: String::from("/foo/bar"),
-            body: String::from("carpe diem."),
+        let mut income = IncomingRequest {
+            method: HttpMethod::POST,
+            path: String::from("/users"),
+            body: String::from("<user id=\"42\">\n  <name>robert</name>\n</user>"),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
-        let tested = Expectation::look_for_expectation(&v, &income);
+        assert_eq!(Some(&exp), Expectation::look_for_expectation(&v, &income));
 
-        assert_eq!(Some(&exp), tested);
+        income.body = String::from("<user id=\"43\"><name>robert</name></user>");
+        assert_eq!(None, Expectation::look_for_expectation(&v, &income));
     }
 
     #[test]
@@ -751,20 +2157,34 @@ mod test {
             method: None,
             path: None,
             body: Some(RequestBody::TEXT {
-                text: String::from("carpe diem."),
+                text: StringMatcher::Exact(String::from("carpe diem.")),
             }),
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -775,9 +2195,10 @@ mod test {
             body: String::from("this is not carpe diem."),
             params: HashMap::new(),
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
-        let v = vec![exp.clone()];
+        let v = vec![exp];
         let tested = Expectation::look_for_expectation(&v, &income);
 
         assert_eq!(None, tested);
@@ -785,28 +2206,39 @@ mod test {
 
     #[test]
     fn test_accept_matching_headers() {
-        let mut headers = HashMap::new();
-        headers.insert(
-            String::from("Content-Type"),
-            String::from("application/json"),
-        );
-
         let req = HttpRequest {
             method: None,
             path: None,
             body: None,
             params: vec![],
-            headers: headers,
+            headers: vec![(
+                String::from("Content-Type"),
+                StringMatcher::Exact(String::from("application/json")),
+            )],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -824,6 +2256,7 @@ mod test {
             body: String::from("carpe diem."),
             params: HashMap::new(),
             headers: incoming_headers,
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
@@ -834,28 +2267,39 @@ mod test {
 
     #[test]
     fn test_refuse_wrong_headers() {
-        let mut headers = HashMap::new();
-        headers.insert(
-            String::from("Content-Type"),
-            String::from("application/json"),
-        );
-
         let req = HttpRequest {
             method: None,
             path: None,
             body: None,
             params: vec![],
-            headers: headers,
+            headers: vec![(
+                String::from("Content-Type"),
+                StringMatcher::Exact(String::from("application/json")),
+            )],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -873,14 +2317,77 @@ mod test {
             body: String::from("carpe diem."),
             params: HashMap::new(),
             headers: incoming_headers,
+            client_certificate: None,
         };
 
-        let v = vec![exp.clone()];
+        let v = vec![exp];
         let tested = Expectation::look_for_expectation(&v, &income);
 
         assert_eq!(None, tested);
     }
 
+    #[test]
+    fn test_accept_matching_headers_ignoring_name_case() {
+        let req = HttpRequest {
+            method: None,
+            path: None,
+            body: None,
+            params: vec![],
+            headers: vec![
+                (
+                    String::from("Authorization"),
+                    StringMatcher::Regex("Bearer [A-Za-z0-9.]+".parse().unwrap()),
+                ),
+                (String::from("X-Request-Id"), StringMatcher::Any),
+            ],
+            matcher: None,
+        };
+
+        let resp = HttpResponse {
+            status_code: Some(200),
+            status_reason: None,
+            body: None,
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
+        };
+
+        let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
+            request: req,
+            response: resp,
+        };
+
+        let mut incoming_headers = HashMap::new();
+        incoming_headers.insert(String::from("authorization"), String::from("Bearer abc.42"));
+        incoming_headers.insert(String::from("x-request-id"), String::from("1"));
+
+        let mut income = IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from("/foo/bar"),
+            body: String::from(""),
+            params: HashMap::new(),
+            headers: incoming_headers,
+            client_certificate: None,
+        };
+
+        let v = vec![exp.clone()];
+        assert_eq!(Some(&exp), Expectation::look_for_expectation(&v, &income));
+
+        income.headers.remove("x-request-id");
+        assert_eq!(None, Expectation::look_for_expectation(&v, &income));
+    }
+
     #[test]
     fn test_accept_matching_params() {
         let req = HttpRequest {
@@ -888,20 +2395,40 @@ mod test {
             path: None,
             body: None,
             params: vec![
-                (String::from("baz"), String::from("foo")),
-                (String::from("baz"), String::from("bar")),
+                (
+                    String::from("baz"),
+                    StringMatcher::Exact(String::from("foo")),
+                ),
+                (
+                    String::from("baz"),
+                    StringMatcher::Exact(String::from("bar")),
+                ),
             ],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -923,8 +2450,9 @@ mod test {
             method: HttpMethod::GET,
             path: String::from("/foo/bar"),
             body: String::from("carpe diem."),
-            params: params,
+            params,
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
         let v = vec![exp.clone()];
@@ -940,20 +2468,40 @@ mod test {
             path: None,
             body: None,
             params: vec![
-                (String::from("baz"), String::from("foo")),
-                (String::from("baz"), String::from("bar")),
+                (
+                    String::from("baz"),
+                    StringMatcher::Exact(String::from("foo")),
+                ),
+                (
+                    String::from("baz"),
+                    StringMatcher::Exact(String::from("bar")),
+                ),
             ],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         };
 
         let resp = HttpResponse {
             status_code: Some(200),
             status_reason: None,
             body: None,
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         };
 
         let exp = Expectation {
+            name: None,
+            tags: vec![],
+            scenario: None,
+            times: None,
+            active_after: None,
+            expires_after: None,
+            responses: None,
             request: req,
             response: resp,
         };
@@ -972,13 +2520,81 @@ mod test {
             method: HttpMethod::GET,
             path: String::from("/foo/bar"),
             body: String::from("carpe diem."),
-            params: params,
+            params,
             headers: HashMap::new(),
+            client_certificate: None,
         };
 
-        let v = vec![exp.clone()];
+        let v = vec![exp];
         let tested = Expectation::look_for_expectation(&v, &income);
 
         assert_eq!(None, tested);
     }
+
+    #[test]
+    fn test_closest_expectations() {
+        let expectations: Vec<Expectation> = serde_dhall::from_str(
+            r###"
+                let Mock = ./dhall/Mock/package.dhall
+                in [ { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.POST
+                                                    , path    = Some (Mock.Path.Exact "/orders")
+                                                    }
+                     , response = Mock.HttpResponse::{=}
+                     }
+                   , { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
+                                                    , path    = Some (Mock.Path.Exact "/users")
+                                                    , params  = [ { key = "page", value = Mock.StringMatcher.Regex "[0-9]+" } ]
+                                                    , headers = [ Mock.matchHeader Mock.contentTypeJSON ]
+                                                    }
+                     , response = Mock.HttpResponse::{=}
+                     }
+                   , { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.DELETE
+                                                    , path    = Some (Mock.Path.Exact "/users")
+                                                    }
+                     , response = Mock.HttpResponse::{=}
+                     }
+                   ]
+            "###,
+        )
+        .parse()
+        .unwrap();
+        let income = IncomingRequest {
+            method: HttpMethod::GET,
+            path: String::from("/users"),
+            body: String::new(),
+            params: vec![(
+                String::from("page"),
+                vec![String::from("first")].into_iter().collect(),
+            )]
+            .into_iter()
+            .collect(),
+            headers: HashMap::new(),
+            client_certificate: None,
+        };
+
+        let closest =
+            Expectation::closest_expectations(&expectations, &income, &Scenarios::default(), 3);
+
+        assert_eq!(3, closest.len());
+        assert_eq!(expectations[2], closest[0].expectation);
+        assert_eq!(vec![Mismatch::Method], closest[0].mismatches);
+        assert_eq!(expectations[0], closest[1].expectation);
+        assert_eq!(
+            vec![Mismatch::Method, Mismatch::Path],
+            closest[1].mismatches
+        );
+        assert_eq!(expectations[1], closest[2].expectation);
+        assert_eq!(
+            vec![
+                Mismatch::Param(String::from("page")),
+                Mismatch::Header(String::from("Content-Type"))
+            ],
+            closest[2].mismatches
+        );
+        assert_eq!(
+            1,
+            Expectation::closest_expectations(&expectations, &income, &Scenarios::default(), 1)
+                .len()
+        );
+    }
 }
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mock::model::Expectation;
+
+/// State of a scenario until an expectation moves it.
+pub const STARTED: &str = "Started";
+
+/// Step of a named scenario made by an expectation.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ScenarioStep {
+    pub name: String,
+    /// State the scenario must be in for the expectation to match, any state when empty
+    #[serde(rename = "requiredState")]
+    pub required_state: Option<String>,
+    /// State the scenario moves to when the expectation answers a request
+    #[serde(rename = "newState")]
+    pub new_state: Option<String>,
+}
+
+/// Current state of the scenarios, a scenario never moved is in the `STARTED` state.
+#[derive(Debug, Default)]
+pub struct Scenarios {
+    states: HashMap<String, String>,
+}
+
+impl Scenarios {
+    pub fn state(&self, name: &str) -> &str {
+        self.states.get(name).map_or(STARTED, String::as_str)
+    }
+
+    /// Is the scenario of `expectation` in its required state, always true without scenario.
+    pub fn accept(&self, expectation: &Expectation) -> bool {
+        match expectation.scenario.as_ref() {
+            Some(ScenarioStep {
+                name,
+                required_state: Some(required),
+                ..
+            }) => self.state(name) == required,
+            _ => true,
+        }
+    }
+
+    /// Move the scenario of `expectation` to its new state.
+    pub fn apply(&mut self, expectation: &Expectation) {
+        if let Some(ScenarioStep {
+            name,
+            new_state: Some(state),
+            ..
+        }) = expectation.scenario.as_ref()
+        {
+            self.states.insert(name.clone(), state.clone());
+        }
+    }
+
+    /// Move all the scenarios back to the `STARTED` state.
+    pub fn reset(&mut self) {
+        self.states.clear();
+    }
+
+    /// Move the scenario `name` back to the `STARTED` state, returns `false` if it isn't known.
+    pub fn reset_scenario<E: AsRef<Expectation>>(
+        &mut self,
+        name: &str,
+        expectations: &[E],
+    ) -> bool {
+        self.states.remove(name).is_some()
+            || expectations
+                .iter()
+                .any(|e| scenario_name(e.as_ref()) == Some(name))
+    }
+
+    /// States of the scenarios moved or used by `expectations`, by scenario name.
+    pub fn states<E: AsRef<Expectation>>(&self, expectations: &[E]) -> BTreeMap<String, String> {
+        expectations
+            .iter()
+            .filter_map(|e| scenario_name(e.as_ref()))
+            .chain(self.states.keys().map(String::as_str))
+            .map(|name| (name.to_string(), self.state(name).to_string()))
+            .collect()
+    }
+}
+
+fn scenario_name(expectation: &Expectation) -> Option<&str> {
+    expectation.scenario.as_ref().map(|step| step.name.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expectations() -> Vec<Expectation> {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in [ Mock.Expectation::{ request  = Mock.HttpRequest::{=}
+                                   , response = Mock.HttpResponse::{=}
+                                   , scenario = Some Mock.Scenario::{ name = "order", requiredState = Some Mock.scenarioStarted, newState = Some "PAID" }
+                                   }
+               , Mock.Expectation::{ request  = Mock.HttpRequest::{=}
+                                   , response = Mock.HttpResponse::{=}
+                                   , scenario = Some Mock.Scenario::{ name = "order", requiredState = Some "PAID" }
+                                   }
+               , Mock.Expectation::{ request  = Mock.HttpRequest::{=}
+                                   , response = Mock.HttpResponse::{=}
+                                   }
+               ]
+        "###;
+        serde_dhall::from_str(data).parse().unwrap()
+    }
+
+    #[test]
+    fn test_move_scenario_state() {
+        let expectations = expectations();
+        let mut scenarios = Scenarios::default();
+
+        assert!(scenarios.accept(&expectations[0]));
+        assert!(!scenarios.accept(&expectations[1]));
+        assert!(scenarios.accept(&expectations[2]));
+
+        scenarios.apply(&expectations[0]);
+
+        assert_eq!("PAID", scenarios.state("order"));
+        assert!(!scenarios.accept(&expectations[0]));
+        assert!(scenarios.accept(&expectations[1]));
+    }
+
+    #[test]
+    fn test_reset_scenarios() {
+        let expectations = expectations();
+        let mut scenarios = Scenarios::default();
+        assert_eq!(
+            vec![(String::from("order"), String::from(STARTED))],
+            scenarios
+                .states(&expectations)
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+
+        scenarios.apply(&expectations[0]);
+        assert!(scenarios.reset_scenario("order", &expectations));
+        assert_eq!(STARTED, scenarios.state("order"));
+        assert!(!scenarios.reset_scenario("payment", &expectations));
+
+        scenarios.apply(&expectations[0]);
+        scenarios.reset();
+        assert_eq!(STARTED, scenarios.state("order"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::convert::TryFrom;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::mock::model::HttpResponse;
+
+/// Responses served in turn by an expectation.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(try_from = "SequenceDefinition")]
+pub enum ResponseSequence {
+    /// Responses in order, the last one answers all the following requests
+    Sequential(Vec<HttpResponse>),
+    /// Responses in order, starting over after the last one
+    Cyclic(Vec<HttpResponse>),
+    /// A random response for each request, with a probability proportional to its weight
+    Weighted(Vec<WeightedResponse>),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct WeightedResponse {
+    pub weight: u64,
+    pub response: HttpResponse,
+}
+
+#[derive(Deserialize)]
+enum SequenceDefinition {
+    Sequential(Vec<HttpResponse>),
+    Cyclic(Vec<HttpResponse>),
+    Weighted(Vec<WeightedResponse>),
+}
+
+impl TryFrom<SequenceDefinition> for ResponseSequence {
+    type Error = String;
+
+    fn try_from(definition: SequenceDefinition) -> Result<Self, Self::Error> {
+        match definition {
+            SequenceDefinition::Sequential(responses) | SequenceDefinition::Cyclic(responses)
+                if responses.is_empty() =>
+            {
+                Err(String::from("Response sequence without response"))
+            }
+            SequenceDefinition::Sequential(responses) => {
+                Ok(ResponseSequence::Sequential(responses))
+            }
+            SequenceDefinition::Cyclic(responses) => Ok(ResponseSequence::Cyclic(responses)),
+            SequenceDefinition::Weighted(responses) if responses.iter().all(|r| r.weight == 0) => {
+                Err(String::from("Weighted responses need a weight above 0"))
+            }
+            SequenceDefinition::Weighted(responses) => Ok(ResponseSequence::Weighted(responses)),
+        }
+    }
+}
+
+impl ResponseSequence {
+    /// All the responses of the sequence.
+    pub fn responses(&self) -> Vec<&HttpResponse> {
+        match self {
+            ResponseSequence::Sequential(responses) | ResponseSequence::Cyclic(responses) => {
+                responses.iter().collect()
+            }
+            ResponseSequence::Weighted(responses) => {
+                responses.iter().map(|r| &r.response).collect()
+            }
+        }
+    }
+
+    /// Response to the `index`-th request (from 0) answered by the expectation.
+    pub fn response(&self, index: u64) -> &HttpResponse {
+        match self {
+            ResponseSequence::Sequential(responses) => {
+                &responses[(index as usize).min(responses.len() - 1)]
+            }
+            ResponseSequence::Cyclic(responses) => &responses[index as usize % responses.len()],
+            ResponseSequence::Weighted(responses) => {
+                let total: u64 = responses.iter().map(|r| r.weight).sum();
+                let mut drawn = rand::thread_rng().gen_range(0, total);
+                for weighted in responses {
+                    if drawn < weighted.weight {
+                        return &weighted.response;
+                    }
+                    drawn -= weighted.weight;
+                }
+                unreachable!("drawn weight {} above the total weight {}", drawn, total)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sequence(mode: &str) -> ResponseSequence {
+        let data = format!(
+            r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.ResponseSequence.{} [ Mock.HttpResponse::{{ statusCode = Some 503 }}, Mock.HttpResponse::{{ statusCode = Some 200 }} ]
+        "###,
+            mode
+        );
+        serde_dhall::from_str(&data).parse().unwrap()
+    }
+
+    fn status_codes(sequence: &ResponseSequence, count: u64) -> Vec<Option<u16>> {
+        (0..count)
+            .map(|index| sequence.response(index).status_code)
+            .collect()
+    }
+
+    #[test]
+    fn test_sequential_responses() {
+        assert_eq!(
+            vec![Some(503), Some(200), Some(200), Some(200)],
+            status_codes(&sequence("Sequential"), 4)
+        );
+    }
+
+    #[test]
+    fn test_cyclic_responses() {
+        assert_eq!(
+            vec![Some(503), Some(200), Some(503), Some(200)],
+            status_codes(&sequence("Cyclic"), 4)
+        );
+    }
+
+    #[test]
+    fn test_weighted_responses() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.ResponseSequence.Weighted [ { weight = 1, response = Mock.HttpResponse::{ statusCode = Some 503 } }
+                                              , { weight = 3, response = Mock.HttpResponse::{ statusCode = Some 200 } }
+                                              , { weight = 0, response = Mock.HttpResponse::{ statusCode = Some 418 } }
+                                              ]
+        "###;
+        let sequence: ResponseSequence = serde_dhall::from_str(data).parse().unwrap();
+
+        let codes = status_codes(&sequence, 4000);
+        let failures = codes.iter().filter(|c| **c == Some(503)).count();
+        assert!((800..=1200).contains(&failures), "{} failures", failures);
+        assert!(!codes.contains(&Some(418)));
+    }
+
+    #[test]
+    fn test_refuse_empty_sequences() {
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.ResponseSequence.Cyclic ([] : List Mock.HttpResponse.Type)
+        "###;
+        assert!(serde_dhall::from_str(data)
+            .parse::<ResponseSequence>()
+            .is_err());
+
+        let data = r###"
+            let Mock = ./dhall/Mock/package.dhall
+            in Mock.ResponseSequence.Weighted [ { weight = 0, response = Mock.HttpResponse::{=} } ]
+        "###;
+        assert!(serde_dhall::from_str(data)
+            .parse::<ResponseSequence>()
+            .is_err());
+    }
+}
@@ -1,23 +1,304 @@
-pub mod json_string {
-    use serde::de::{self, Deserialize, DeserializeOwned, Deserializer};
+/// JSON document, received either as a JSON text or as a list of `JsonNode` built from a
+/// dhall `JSON.Type`, and exposed as a JSON text.
+pub mod json_value {
+    use serde::de::{self, Deserializer, SeqAccess, Visitor};
     use serde::ser::{self, Serialize, Serializer};
-    use serde_json;
+    use serde_json::Value;
+    use std::fmt;
 
-    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    use crate::mock::json::{self, JsonNode};
+
+    pub fn serialize<S>(value: &Value, serializer: S) -> Result<S::Ok, S::Error>
     where
-        T: Serialize,
         S: Serializer,
     {
         let j = serde_json::to_string(value).map_err(ser::Error::custom)?;
         j.serialize(serializer)
     }
 
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(JsonVisitor)
+    }
+
+    struct JsonVisitor;
+
+    impl<'de> Visitor<'de> for JsonVisitor {
+        type Value = Value;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a JSON text or a list of JSON nodes")
+        }
+
+        fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            serde_json::from_str(v).map_err(de::Error::custom)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut nodes: Vec<JsonNode> = vec![];
+            while let Some(node) = seq.next_element()? {
+                nodes.push(node);
+            }
+            json::from_nodes(nodes).map_err(de::Error::custom)
+        }
+    }
+
+    pub mod option {
+        use super::JsonVisitor;
+        use serde::de::{Deserializer, Visitor};
+        use serde::ser::Serializer;
+        use serde_json::Value;
+        use std::fmt;
+
+        pub fn serialize<S>(value: &Option<Value>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match value {
+                Some(value) => serializer.serialize_some(&value.to_string()),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_option(OptionVisitor)
+        }
+
+        struct OptionVisitor;
+
+        impl<'de> Visitor<'de> for OptionVisitor {
+            type Value = Option<Value>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an optional JSON text or list of JSON nodes")
+            }
+
+            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                deserializer.deserialize_any(JsonVisitor).map(Some)
+            }
+
+            fn visit_none<E>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+        }
+    }
+}
+
+pub mod parsed_string {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::Serializer;
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display,
+        S: Serializer,
+    {
+        serializer.collect_str(value)
+    }
+
     pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
     where
-        T: DeserializeOwned,
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Request matcher tree, received as a list of nodes from dhall and exposed as a tree.
+/// The tree form is also accepted, to read back json documents.
+pub mod matcher_nodes {
+    use serde::de::value::MapAccessDeserializer;
+    use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+    use serde::ser::{Serialize, Serializer};
+    use std::fmt;
+
+    use crate::mock::matcher::{RequestMatcher, RequestMatcherNode};
+
+    pub fn serialize<S>(value: &Option<RequestMatcher>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<RequestMatcher>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(MatcherVisitor)
+    }
+
+    struct MatcherVisitor;
+
+    impl<'de> Visitor<'de> for MatcherVisitor {
+        type Value = Option<RequestMatcher>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of request matcher nodes or a request matcher")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut nodes: Vec<RequestMatcherNode> = vec![];
+            while let Some(node) = seq.next_element()? {
+                nodes.push(node);
+            }
+            if nodes.is_empty() {
+                return Ok(None);
+            }
+            RequestMatcher::from_nodes(nodes)
+                .map(Some)
+                .map_err(de::Error::custom)
+        }
+
+        fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            RequestMatcher::deserialize(MapAccessDeserializer::new(map)).map(Some)
+        }
+
+        fn visit_unit<E>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+    }
+}
+
+/// Name / value pairs, received from dhall as a list of `{ mapKey, mapValue }` records and exposed
+/// as a list of pairs. serde_dhall reads these records as a map before they reach the deserializer :
+/// the pairs come sorted by name and only the last value of a repeated name is kept (see `headers`).
+pub mod entries {
+    use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+    use serde::ser::{Serialize, Serializer};
+    use std::fmt;
+    use std::marker::PhantomData;
+
+    pub fn serialize<V, S>(value: &[(String, V)], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        V: Serialize,
+        S: Serializer,
+    {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, V, D>(deserializer: D) -> Result<Vec<(String, V)>, D::Error>
+    where
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(EntriesVisitor(PhantomData))
+    }
+
+    struct EntriesVisitor<V>(PhantomData<V>);
+
+    impl<'de, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<V> {
+        type Value = Vec<(String, V)>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of mapKey / mapValue records or a list of pairs")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            let mut entries = vec![];
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut entries = vec![];
+            while let Some(entry) = seq.next_element()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// Http headers, read like `entries`. A value holding several lines (built by `Mock.repeatedHeader`)
+/// is split into one header per line : line breaks aren't allowed in header values and serde_dhall
+/// keeps only the last value of a repeated `mapKey`.
+pub mod headers {
+    use serde::de::Deserializer;
+    use serde::ser::Serializer;
+
+    pub fn serialize<S>(value: &[(String, String)], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        super::entries::serialize(value, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<(String, String)>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let entries: Vec<(String, String)> = super::entries::deserialize(deserializer)?;
+        Ok(entries
+            .into_iter()
+            .flat_map(|(name, value)| {
+                value
+                    .split('\n')
+                    .map(|line| (name.clone(), line.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+}
+
+pub mod rfc3339 {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::ser::Serializer;
+    use std::time::SystemTime;
+
+    pub fn serialize<S>(value: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(&humantime::format_rfc3339_millis(*value))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+    where
         D: Deserializer<'de>,
     {
-        let j = String::deserialize(deserializer)?;
-        serde_json::from_str(&j).map_err(de::Error::custom)
+        let s = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339_weak(&s).map_err(de::Error::custom)
     }
 }
@@ -1,21 +1,51 @@
-use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::fmt::Debug;
+use std::iter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime};
 
 use lazy_static::lazy_static;
 use rayon::{ThreadPool, ThreadPoolBuilder};
 use retry::retry;
 
-use log::info;
+use log::{info, warn};
 
 use anyhow::{anyhow, Context, Error};
 
-use super::compilation::compile_configuration;
-use super::model::{Expectation, IncomingRequest};
+use super::compilation::{compile_configuration, compile_configuration_file, compile_dhall};
+use super::journal::{Journal, JournalEntry, JournalExpectation};
+use super::model::{Counter, Expectation, ExpectationEntry, ExpectationMiss, IncomingRequest};
+use super::scenario::Scenarios;
+use super::verification::Verification;
 use retry::delay::{jitter, Exponential};
 use tokio::sync::oneshot;
 
 pub struct State {
-    pub expectations: Vec<Expectation>,
+    pub expectations: Vec<ExpectationEntry>,
+    /// Locked on its own, recording a request doesn't block the other requests matching
+    pub journal: Mutex<Journal>,
+    pub scenarios: Scenarios,
+    /// Configuration files in load order, a reloaded file keeps its position among them
+    pub sources: Vec<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            expectations: vec![],
+            journal: Mutex::new(Journal::default()),
+            scenarios: Scenarios::default(),
+            sources: vec![],
+        }
+    }
+}
+
+impl State {
+    pub fn journal(&self) -> Result<MutexGuard<Journal>, Error> {
+        self.journal
+            .lock()
+            .map_err(|_| anyhow!("Error acquiring lock on journal"))
+    }
 }
 
 pub type SharedState = Arc<RwLock<State>>;
@@ -28,29 +58,190 @@ lazy_static! {
         .unwrap();
 }
 
+static NEXT_EXPECTATION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Time between two removals of the expired expectations.
+pub const EXPIRED_COLLECTION_INTERVAL: Duration = Duration::from_secs(1);
+
+fn write_state(state: &SharedState) -> Result<RwLockWriteGuard<State>, Error> {
+    retry(Exponential::from_millis(10).map(jitter).take(3), || {
+        state.write()
+    })
+    .map_err(|_| anyhow!("Can't acquire write lock on state"))
+}
+
+/// Expectations compiled from a configuration `source` (file path or web configuration).
+#[derive(Debug)]
+pub struct Configuration {
+    pub source: String,
+    pub expectations: Vec<Expectation>,
+}
+
+fn new_entries(configuration: Configuration) -> Vec<ExpectationEntry> {
+    let Configuration {
+        source,
+        expectations,
+    } = configuration;
+    let loaded_at = SystemTime::now();
+    expectations
+        .into_iter()
+        .map(|expectation| ExpectationEntry {
+            id: NEXT_EXPECTATION_ID.fetch_add(1, Ordering::Relaxed),
+            source: source.clone(),
+            loaded_at,
+            remaining: expectation.times.map(Counter::new),
+            served: Counter::default(),
+            expectation,
+        })
+        .collect()
+}
+
 // Todo add Unit tests
 pub async fn add_expectations_in_state(
     state: SharedState,
-    mut expectations: Vec<Expectation>,
+    configuration: Configuration,
 ) -> Result<(), Error> {
-    let mut state = retry(Exponential::from_millis(10).map(jitter).take(3), || {
-        state.write()
-    })
-    .map_err(|_| anyhow!("Can't acquire write lock on state"))?;
-    state.expectations.append(&mut expectations);
+    let mut entries = new_entries(configuration);
+    write_state(&state)?.expectations.append(&mut entries);
+    Ok(())
+}
+
+/// Replace all the expectations of the state at once. The configuration files are detached from
+/// the state : their changes aren't reloaded anymore.
+pub async fn replace_expectations_in_state(
+    state: SharedState,
+    configuration: Configuration,
+) -> Result<(), Error> {
+    let entries = new_entries(configuration);
+    let mut state = write_state(&state)?;
+    state.expectations = entries;
+    state.sources.clear();
+    Ok(())
+}
+
+/// Replace the expectations loaded from the configuration file of `configuration`, keeping their
+/// priority over the expectations loaded after them. A file without expectations yet (ex: its
+/// first load failed) is inserted before the expectations of the files following it in `sources`.
+/// Returns `false`, without changing the state, if the file isn't in `sources` anymore.
+pub async fn replace_source_expectations_in_state(
+    state: SharedState,
+    configuration: Configuration,
+) -> Result<bool, Error> {
+    let source = configuration.source.clone();
+    let entries = new_entries(configuration);
+    let mut state = write_state(&state)?;
+    let State {
+        expectations,
+        sources,
+        ..
+    } = &mut *state;
+    let rank = |s: &str| sources.iter().position(|source| source == s);
+    let source_rank = match rank(&source) {
+        Some(source_rank) => source_rank,
+        None => return Ok(false),
+    };
+    let position = expectations
+        .iter()
+        .position(|e| e.source == source || rank(&e.source).map_or(true, |rank| rank > source_rank))
+        .unwrap_or_else(|| expectations.len());
+    expectations.retain(|e| e.source != source);
+    expectations.splice(position..position, entries);
+    Ok(true)
+}
+
+/// Remove the expectations expired at `now`, returns the number of removed expectations.
+pub async fn remove_expired_expectations_from_state(
+    state: SharedState,
+    now: SystemTime,
+) -> Result<usize, Error> {
+    let mut state = write_state(&state)?;
+    let count = state.expectations.len();
+    state.expectations.retain(|e| !e.is_expired(now));
+    Ok(count - state.expectations.len())
+}
+
+/// Remove the expired expectations from the state every `interval`.
+pub async fn collect_expired_expectations(state: SharedState, interval: Duration) {
+    loop {
+        tokio::time::delay_for(interval).await;
+        match remove_expired_expectations_from_state(state.clone(), SystemTime::now()).await {
+            Ok(0) => (),
+            Ok(count) => info!("{} expired expectations removed", count),
+            Err(e) => warn!("Error removing expired expectations : {:#}", e),
+        }
+    }
+}
+
+pub async fn clear_expectations_in_state(state: SharedState) -> Result<(), Error> {
+    write_state(&state)?.expectations.clear();
     Ok(())
 }
 
+/// Remove the expectation `id` from the state, returns `false` if it doesn't exist.
+pub async fn remove_expectation_from_state(state: SharedState, id: u64) -> Result<bool, Error> {
+    let mut state = write_state(&state)?;
+    let count = state.expectations.len();
+    state.expectations.retain(|e| e.id != id);
+    Ok(state.expectations.len() != count)
+}
+
+/// Move the scenario `name` back to its initial state, returns `false` if it doesn't exist.
+pub async fn reset_scenario_in_state(state: SharedState, name: &str) -> Result<bool, Error> {
+    let mut state = write_state(&state)?;
+    let State {
+        expectations,
+        scenarios,
+        ..
+    } = &mut *state;
+    Ok(scenarios.reset_scenario(name, expectations))
+}
+
 pub async fn load_dhall_expectation(
     id: String,
     dhall_content: String,
-) -> Result<Vec<Expectation>, Error> {
+) -> Result<Configuration, Error> {
+    let expectations = load_dhall(id.clone(), dhall_content, compile_configuration).await?;
+    Ok(Configuration {
+        source: id,
+        expectations,
+    })
+}
+
+/// Load the configuration file `configuration_name`, its relative imports are resolved from its
+/// folder (unlike a configuration content, resolved from the working directory).
+pub async fn load_dhall_expectation_file(
+    configuration_name: String,
+) -> Result<Configuration, Error> {
+    let expectations = load_dhall(
+        configuration_name.clone(),
+        configuration_name.clone(),
+        compile_configuration_file,
+    )
+    .await?;
+    Ok(Configuration {
+        source: configuration_name,
+        expectations,
+    })
+}
+
+pub async fn load_dhall_verification(
+    id: String,
+    dhall_content: String,
+) -> Result<Verification, Error> {
+    load_dhall(id, dhall_content, compile_dhall).await
+}
+
+/// Compile `dhall_input` (a Dhall content or file name, depending on `compile`) on the dhall pool.
+async fn load_dhall<T: Debug + Send + 'static>(
+    id: String,
+    dhall_input: String,
+    compile: fn(&str) -> Result<T, Error>,
+) -> Result<T, Error> {
     let (s, r) = oneshot::channel();
     POOL.spawn(move || {
         info!("Start load {} config", id);
         let now = Instant::now();
-        let result =
-            compile_configuration(&dhall_content).context(format!("Error compiling {}", id));
+        let result = compile(&dhall_input).context(format!("Error compiling {}", id));
         info!("Loaded {}, in {} secs", id, now.elapsed().as_secs());
         s.send(result)
             .expect("Internal error on communication between app and dhall runtimes");
@@ -59,14 +250,104 @@ pub async fn load_dhall_expectation(
         .expect("Internal error on communication between app and dhall runtimes")
 }
 
+fn read_state(state: &SharedState) -> Result<RwLockReadGuard<State>, Error> {
+    state
+        .read()
+        .map_err(|_| anyhow!("Error acquiring read on shared state"))
+}
+
+/// Look for the expectation answering `request`, count the request and move its scenario.
+/// The request is matched and counted under the read lock, the count of a limited expectation
+/// is decremented atomically. The write lock is only taken for an expectation with a scenario, to
+/// check again its state and move it, so concurrent requests see each scenario state once.
 // Todo add Unit tests
 pub async fn search_for_mock(
-    request: IncomingRequest,
+    request: &IncomingRequest,
     state: SharedState,
-) -> Result<Option<Expectation>, Error> {
-    let state = state
-        .read()
-        .map_err(|_| anyhow!("Error acquiring read on shared state"))?;
+) -> Result<Option<ExpectationEntry>, Error> {
+    let now = SystemTime::now();
+    let candidates: Vec<u64> = {
+        let state = read_state(&state)?;
+        let mut matching = state.expectations.iter().filter(|e| {
+            !e.is_exhausted()
+                && e.is_active(now)
+                && state.scenarios.accept(&e.expectation)
+                && e.expectation.test(request)
+        });
+        loop {
+            match matching.next() {
+                None => return Ok(None),
+                Some(entry) if entry.expectation.scenario.is_none() => {
+                    if let Some(claimed) = entry.claim() {
+                        return Ok(Some(claimed));
+                    }
+                }
+                Some(entry) => break iter::once(entry).chain(matching).map(|e| e.id).collect(),
+            }
+        }
+    };
+
+    let mut state = write_state(&state)?;
+    let State {
+        expectations,
+        scenarios,
+        ..
+    } = &mut *state;
+    for id in candidates {
+        let claimed = expectations
+            .iter()
+            .find(|e| e.id == id)
+            .filter(|e| scenarios.accept(&e.expectation))
+            .and_then(ExpectationEntry::claim);
+        if let Some(claimed) = claimed {
+            scenarios.apply(&claimed.expectation);
+            return Ok(Some(claimed));
+        }
+    }
+    Ok(None)
+}
+
+/// Number of expectations reported for a request without matching expectation.
+const CLOSEST_EXPECTATIONS: usize = 3;
+
+pub async fn diagnose_request(
+    request: &IncomingRequest,
+    state: SharedState,
+) -> Result<Vec<ExpectationMiss<ExpectationEntry>>, Error> {
+    let state = read_state(&state)?;
 
-    Ok(Expectation::look_for_expectation(&state.expectations, &request).cloned())
+    let now = SystemTime::now();
+    let available: Vec<&ExpectationEntry> = state
+        .expectations
+        .iter()
+        .filter(|e| !e.is_exhausted() && e.is_active(now))
+        .collect();
+    Ok(Expectation::closest_expectations(
+        &available,
+        request,
+        &state.scenarios,
+        CLOSEST_EXPECTATIONS,
+    )
+    .into_iter()
+    .map(|miss| ExpectationMiss {
+        expectation: miss.expectation.clone(),
+        mismatches: miss.mismatches,
+    })
+    .collect())
+}
+
+/// Record `request` in the journal, under the read lock of the state.
+pub async fn record_request(
+    state: SharedState,
+    request: IncomingRequest,
+    expectation: Option<&ExpectationEntry>,
+    status_code: u16,
+) -> Result<(), Error> {
+    read_state(&state)?.journal()?.record(JournalEntry {
+        timestamp: SystemTime::now(),
+        request,
+        expectation: expectation.map(JournalExpectation::from),
+        status_code,
+    });
+    Ok(())
 }
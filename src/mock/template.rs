@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use rand::distributions::Uniform;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::mock::model::IncomingRequest;
+use crate::mock::serde as serde_mock;
+
+/// Response text with `{{expression}}` parts rendered from the incoming request, ex:
+/// `{ "id": "{{path.id}}", "name": "{{body.user.name}}", "at": "{{now()}}" }`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Part {
+    Literal(String),
+    Expression(Expression),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Expression {
+    Method,
+    Path,
+    Body,
+    /// Segment captured by the expectation path template
+    PathSegment(String),
+    Query(String),
+    Header(String),
+    /// Field of the JSON body, array items are selected by index
+    BodyField(Vec<String>),
+    Now,
+    Uuid,
+    Random(i64, i64),
+}
+
+/// Request data available to the templates of a response.
+pub struct TemplateContext<'a> {
+    request: &'a IncomingRequest,
+    captures: HashMap<String, String>,
+    json: Option<Value>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(request: &'a IncomingRequest, captures: HashMap<String, String>) -> Self {
+        TemplateContext {
+            request,
+            captures,
+            json: serde_json::from_str(&request.body).ok(),
+        }
+    }
+}
+
+impl Template {
+    /// Render the template, missing request values are rendered as empty strings.
+    pub fn render(&self, context: &TemplateContext) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Expression(expression) => expression.render(context),
+            })
+            .collect()
+    }
+}
+
+impl Expression {
+    fn parse(expression: &str) -> Result<Expression, String> {
+        if let Some(call) = expression.strip_suffix(')') {
+            let (name, args) = call
+                .split_once('(')
+                .ok_or_else(|| format!("Invalid template function {}", expression))?;
+            let args: Vec<&str> = args
+                .split(',')
+                .map(str::trim)
+                .filter(|a| !a.is_empty())
+                .collect();
+            return match (name.trim(), args.as_slice()) {
+                ("now", []) => Ok(Expression::Now),
+                ("uuid", []) => Ok(Expression::Uuid),
+                ("random", [min, max]) => {
+                    let min = parse_integer(min)?;
+                    let max = parse_integer(max)?;
+                    if min > max {
+                        return Err(format!("random({}, {}) : min is above max", min, max));
+                    }
+                    Ok(Expression::Random(min, max))
+                }
+                _ => Err(format!("Unknown template function {}", expression)),
+            };
+        }
+        let (root, field) = match expression.split_once('.') {
+            Some((root, field)) if !field.is_empty() => (root, Some(field)),
+            Some(_) => return Err(format!("Missing field in {}", expression)),
+            None => (expression, None),
+        };
+        match (root, field) {
+            ("method", None) => Ok(Expression::Method),
+            ("path", None) => Ok(Expression::Path),
+            ("body", None) => Ok(Expression::Body),
+            ("path", Some(name)) => Ok(Expression::PathSegment(name.to_string())),
+            ("query", Some(key)) => Ok(Expression::Query(key.to_string())),
+            ("header", Some(name)) => Ok(Expression::Header(name.to_string())),
+            ("body", Some(fields)) => Ok(Expression::BodyField(
+                fields.split('.').map(String::from).collect(),
+            )),
+            _ => Err(format!("Unknown template expression {}", expression)),
+        }
+    }
+
+    fn render(&self, context: &TemplateContext) -> String {
+        let request = context.request;
+        match self {
+            Expression::Method => format!("{:?}", request.method),
+            Expression::Path => request.path.clone(),
+            Expression::Body => request.body.clone(),
+            Expression::PathSegment(name) => {
+                context.captures.get(name).cloned().unwrap_or_default()
+            }
+            Expression::Query(key) => request
+                .params
+                .get(key)
+                .and_then(|values| values.iter().next())
+                .cloned()
+                .unwrap_or_default(),
+            Expression::Header(name) => request
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.clone())
+                .unwrap_or_default(),
+            Expression::BodyField(fields) => context
+                .json
+                .as_ref()
+                .and_then(|json| {
+                    fields.iter().try_fold(json, |value, field| match value {
+                        Value::Array(items) => field.parse().ok().and_then(|i: usize| items.get(i)),
+                        value => value.get(field),
+                    })
+                })
+                .map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                })
+                .unwrap_or_default(),
+            Expression::Now => humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            Expression::Uuid => uuid(),
+            Expression::Random(min, max) => rand::thread_rng()
+                .sample(Uniform::new_inclusive(*min, *max))
+                .to_string(),
+        }
+    }
+}
+
+fn parse_integer(value: &str) -> Result<i64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} isn't an integer", value))
+}
+
+/// Random (version 4) uuid.
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| format!("Unclosed {{{{ in template {}", source))?;
+            let expression = Expression::parse(rest[start + 2..start + end].trim())
+                .map_err(|e| format!("{} in template {}", e, source))?;
+            parts.push(Part::Expression(expression));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Template {
+            source: source.to_string(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_mock::parsed_string::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        serde_mock::parsed_string::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::model::HttpMethod;
+
+    fn request() -> IncomingRequest {
+        IncomingRequest {
+            method: HttpMethod::POST,
+            path: String::from("/users/42"),
+            body: String::from(
+                r#"{ "user": { "name": "robert", "roles": ["admin"], "age": 42 } }"#,
+            ),
+            headers: vec![(String::from("x-request-id"), String::from("abc"))]
+                .into_iter()
+                .collect(),
+            params: vec![(
+                String::from("page"),
+                vec![String::from("2")].into_iter().collect(),
+            )]
+            .into_iter()
+            .collect(),
+            client_certificate: None,
+        }
+    }
+
+    fn render(template: &str) -> String {
+        let request = request();
+        let captures = vec![(String::from("id"), String::from("42"))]
+            .into_iter()
+            .collect();
+        template
+            .parse::<Template>()
+            .unwrap()
+            .render(&TemplateContext::new(&request, captures))
+    }
+
+    #[test]
+    fn test_render_request_values() {
+        assert_eq!(
+            "POST /users/42 id=42 page=2 request=abc",
+            render("{{method}} {{path}} id={{ path.id }} page={{query.page}} request={{header.X-Request-Id}}")
+        );
+        assert_eq!(
+            r#"robert admin 42 {"name":"robert","roles":["admin"],"age":42}"#,
+            render("{{body.user.name}} {{body.user.roles.0}} {{body.user.age}} {{body.user}}")
+        );
+        assert_eq!("[]", render("[{{query.missing}}{{body.user.missing}}]"));
+        assert_eq!(request().body, render("{{body}}"));
+    }
+
+    #[test]
+    fn test_render_helpers() {
+        assert!(humantime::parse_rfc3339(&render("{{now()}}")).is_ok());
+        let uuid = render("{{uuid()}}");
+        assert_eq!(36, uuid.len());
+        assert_eq!(Some('4'), uuid.chars().nth(14));
+        let random: i64 = render("{{random(1, 3)}}").parse().unwrap();
+        assert!((1..=3).contains(&random));
+        let random: i64 = render("{{random(0, 9223372036854775807)}}")
+            .parse()
+            .unwrap();
+        assert!(random >= 0);
+        assert!(
+            render("{{random(-9223372036854775808, 9223372036854775807)}}")
+                .parse::<i64>()
+                .is_ok()
+        );
+        assert_eq!("7", render("{{random(7, 7)}}"));
+    }
+
+    #[test]
+    fn test_refuse_invalid_template() {
+        assert!("{{path.id".parse::<Template>().is_err());
+        assert!("{{cookie.session}}".parse::<Template>().is_err());
+        assert!("{{random(3, 1)}}".parse::<Template>().is_err());
+        assert!("{{random(1)}}".parse::<Template>().is_err());
+        assert!("{{today()}}".parse::<Template>().is_err());
+        assert!("{{query.}}".parse::<Template>().is_err());
+    }
+}
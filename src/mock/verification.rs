@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mock::journal::{Journal, JournalEntry};
+use crate::mock::model::{HttpRequest, Mismatch};
+
+/// Number of recorded requests reported when a verification fails.
+const CLOSEST_REQUESTS: usize = 5;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum CallCount {
+    Exactly(usize),
+    AtLeast(usize),
+    AtMost(usize),
+    Never,
+}
+
+impl CallCount {
+    pub fn accept(&self, count: usize) -> bool {
+        match self {
+            CallCount::Exactly(expected) => count == *expected,
+            CallCount::AtLeast(expected) => count >= *expected,
+            CallCount::AtMost(expected) => count <= *expected,
+            CallCount::Never => count == 0,
+        }
+    }
+}
+
+/// Assertion on the number of recorded requests matching `request`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Verification {
+    pub request: HttpRequest,
+    pub count: CallCount,
+}
+
+/// Recorded request with the criteria it doesn't match.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct NearMiss<'a> {
+    #[serde(flatten)]
+    pub entry: &'a JournalEntry,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct VerificationReport<'a> {
+    pub passed: bool,
+    pub count: usize,
+    /// Recorded requests with the fewest mismatches, only filled when the verification fails.
+    pub closest: Vec<NearMiss<'a>>,
+}
+
+impl Verification {
+    pub fn verify<'a>(&self, journal: &'a Journal) -> VerificationReport<'a> {
+        let mut requests: Vec<NearMiss<'a>> = journal
+            .entries()
+            .map(|entry| NearMiss {
+                entry,
+                mismatches: self.request.mismatches(&entry.request),
+            })
+            .collect();
+        let count = requests.iter().filter(|r| r.mismatches.is_empty()).count();
+        let passed = self.count.accept(count);
+
+        let closest = if passed {
+            vec![]
+        } else {
+            requests.sort_by_key(|r| r.mismatches.len());
+            requests.truncate(CLOSEST_REQUESTS);
+            requests
+        };
+        VerificationReport {
+            passed,
+            count,
+            closest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::model::{HttpMethod, IncomingRequest};
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    fn journal(requests: &[(HttpMethod, &str, &str)]) -> Journal {
+        let mut journal = Journal::default();
+        for (method, path, body) in requests {
+            journal.record(JournalEntry {
+                timestamp: SystemTime::now(),
+                request: IncomingRequest {
+                    method: *method,
+                    path: path.to_string(),
+                    body: body.to_string(),
+                    headers: HashMap::new(),
+                    params: HashMap::new(),
+                    client_certificate: None,
+                },
+                expectation: None,
+                status_code: 404,
+            });
+        }
+        journal
+    }
+
+    fn verification(count: &str) -> Verification {
+        serde_dhall::from_str(&format!(
+            r###"
+                let Mock = ./dhall/Mock/package.dhall
+                in {{ request = Mock.HttpRequest::{{ method = Some Mock.HttpMethod.POST
+                                                  , path   = Some (Mock.Path.Exact "/payments")
+                                                  , body   = Some (Mock.Body.JSONSubset {{ json = "{{ \"amount\": 100 }}", arrays = Mock.ArrayMatching.Strict }})
+                                                  }}
+                   , count   = Mock.CallCount.{}
+                   }}
+            "###,
+            count
+        ))
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_call_count() {
+        assert!(CallCount::Exactly(2).accept(2));
+        assert!(!CallCount::Exactly(2).accept(3));
+        assert!(CallCount::AtLeast(2).accept(3));
+        assert!(!CallCount::AtLeast(2).accept(1));
+        assert!(CallCount::AtMost(2).accept(0));
+        assert!(!CallCount::AtMost(2).accept(3));
+        assert!(CallCount::Never.accept(0));
+        assert!(!CallCount::Never.accept(1));
+    }
+
+    #[test]
+    fn test_verify_passed() {
+        let journal = journal(&[
+            (
+                HttpMethod::POST,
+                "/payments",
+                r#"{ "amount": 100, "currency": "EUR" }"#,
+            ),
+            (HttpMethod::GET, "/payments", ""),
+        ]);
+
+        let report = verification("Exactly 1").verify(&journal);
+
+        assert!(report.passed);
+        assert_eq!(1, report.count);
+        assert!(report.closest.is_empty());
+    }
+
+    #[test]
+    fn test_verify_failed_with_closest_requests() {
+        let journal = journal(&[
+            (HttpMethod::GET, "/users", ""),
+            (HttpMethod::POST, "/payments", r#"{ "amount": 99 }"#),
+        ]);
+
+        let report = verification("AtLeast 1").verify(&journal);
+
+        assert!(!report.passed);
+        assert_eq!(0, report.count);
+        assert_eq!(2, report.closest.len());
+        assert_eq!("/payments", report.closest[0].entry.request.path);
+        assert_eq!(vec![Mismatch::Body], report.closest[0].mismatches);
+        assert_eq!(
+            vec![Mismatch::Method, Mismatch::Path, Mismatch::Body],
+            report.closest[1].mismatches
+        );
+    }
+}
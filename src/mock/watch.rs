@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use regex::Regex;
+
+use super::service::{
+    load_dhall_expectation_file, replace_source_expectations_in_state, SharedState,
+};
+
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref LOCAL_IMPORT: Regex =
+        Regex::new(r"(?:^|[\s(\[,=])((?:\.\.?|~)?/[^/\s()\[\]{},][^\s()\[\]{},]*)").unwrap();
+}
+
+/// Local files imported by a Dhall `content`, relative imports are resolved from `base`.
+pub fn local_imports(content: &str, base: &Path) -> Vec<PathBuf> {
+    LOCAL_IMPORT
+        .captures_iter(content)
+        .map(|c| {
+            let import = &c[1];
+            match import.strip_prefix("~/") {
+                Some(path) => std::env::var("HOME")
+                    .map(|home| Path::new(&home).join(path))
+                    .unwrap_or_else(|_| PathBuf::from(import)),
+                None if import.starts_with('/') => PathBuf::from(import),
+                None => base.join(import),
+            }
+        })
+        .collect()
+}
+
+/// The configuration file and all the local files it imports, directly or not, relative imports
+/// are resolved from the importing file directory like the configuration compilation does.
+pub fn watched_files(configuration_name: &str) -> BTreeSet<PathBuf> {
+    let mut files = BTreeSet::new();
+    let file = PathBuf::from(configuration_name);
+    let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut pending = vec![(file, dir)];
+    while let Some((file, base)) = pending.pop() {
+        if let Ok(content) = fs::read_to_string(&file) {
+            for import in local_imports(&content, &base) {
+                if !files.contains(&import) {
+                    let dir = import.parent().map(Path::to_path_buf).unwrap_or_default();
+                    pending.push((import, dir));
+                }
+            }
+        }
+        files.insert(file);
+    }
+    files
+}
+
+fn modification_times(files: &BTreeSet<PathBuf>) -> BTreeMap<PathBuf, Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| (f.clone(), fs::metadata(f).and_then(|m| m.modified()).ok()))
+        .collect()
+}
+
+/// Returns `false` if the configuration has been detached from the state.
+async fn reload_configuration_file(
+    state: SharedState,
+    configuration_name: &str,
+) -> Result<bool, Error> {
+    let configuration = load_dhall_expectation_file(configuration_name.to_string()).await?;
+    replace_source_expectations_in_state(state, configuration).await
+}
+
+/// The files of a configuration and their modification times, taken before its first load so
+/// the changes made while it loads are reloaded once it's watched.
+pub struct ConfigurationWatch {
+    configuration_name: String,
+    files: BTreeSet<PathBuf>,
+    times: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+impl ConfigurationWatch {
+    pub fn new(configuration_name: String) -> Self {
+        let files = watched_files(&configuration_name);
+        let times = modification_times(&files);
+        ConfigurationWatch {
+            configuration_name,
+            files,
+            times,
+        }
+    }
+
+    /// Reload the expectations of the configuration each time it or one of its local imports
+    /// changes, the previous expectations are kept when the new configuration doesn't compile.
+    pub async fn watch(self, state: SharedState, interval: Duration) {
+        let ConfigurationWatch {
+            configuration_name,
+            mut files,
+            mut times,
+        } = self;
+        info!(
+            "Watching {} and its imports {:?}",
+            configuration_name, files
+        );
+        loop {
+            tokio::time::delay_for(interval).await;
+            let new_times = modification_times(&files);
+            if new_times == times {
+                continue;
+            }
+            match reload_configuration_file(state.clone(), &configuration_name).await {
+                Ok(true) => info!("Configuration {} reloaded", configuration_name),
+                Ok(false) => {
+                    info!(
+                        "Configuration {} replaced by the admin server, not watched anymore",
+                        configuration_name
+                    );
+                    return;
+                }
+                Err(e) => warn!(
+                    "Error reloading configuration {}, previous version kept : {:#}",
+                    configuration_name, e
+                ),
+            }
+            files = watched_files(&configuration_name);
+            times = modification_times(&files);
+        }
+    }
+}
+
+/// Reload the expectations of `configuration_name` each time it or one of its local imports
+/// changes, the previous expectations are kept when the new configuration doesn't compile.
+pub async fn watch_configuration_file(
+    state: SharedState,
+    configuration_name: String,
+    interval: Duration,
+) {
+    ConfigurationWatch::new(configuration_name)
+        .watch(state, interval)
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::service::{
+        add_expectations_in_state, load_dhall_expectation, replace_expectations_in_state, State,
+    };
+    use std::sync::{Arc, RwLock};
+
+    fn configuration(path: &str) -> String {
+        format!(
+            r###"
+                let Mock = {}/dhall/Mock/package.dhall
+                in [ {{ request  = Mock.HttpRequest::{{ path = Some (Mock.Path.Exact "{}") }}
+                     , response = Mock.HttpResponse::{{=}}
+                     }}
+                   ]
+            "###,
+            env!("CARGO_MANIFEST_DIR"),
+            path
+        )
+    }
+
+    fn paths(state: &SharedState) -> Vec<String> {
+        state
+            .read()
+            .unwrap()
+            .expectations
+            .iter()
+            .map(|e| format!("{:?}", e.expectation.request.path))
+            .collect()
+    }
+
+    #[test]
+    fn test_local_imports() {
+        let content = r###"
+            let Prelude = https://prelude.dhall-lang.org/package.dhall
+            let Mock = ./Mock/package.dhall sha256:79dfc281a05bc7b78f927e0da0c274ee5709b1c55c9e5f59499cb28e9d6f3ec0
+            let users = (../users.dhall)
+            let absolute = [/etc/mock.dhall]
+            in Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/users") }
+        "###;
+
+        assert_eq!(
+            vec![
+                PathBuf::from("conf/./Mock/package.dhall"),
+                PathBuf::from("conf/../users.dhall"),
+                PathBuf::from("/etc/mock.dhall"),
+            ],
+            local_imports(content, Path::new("conf"))
+        );
+    }
+
+    #[test]
+    fn test_watched_files_follow_nested_imports() {
+        let files = watched_files("./dhall/static.dhall");
+
+        assert!(files.contains(Path::new("./dhall/static.dhall")));
+        assert!(files.contains(Path::new("./dhall/Mock/package.dhall")));
+    }
+
+    #[tokio::test]
+    async fn test_reload_configuration_outside_working_directory() {
+        let dir = std::env::temp_dir().join(format!("dhall-mock-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("watched.dhall");
+        let name = file.to_string_lossy().to_string();
+        fs::write(dir.join("path.dhall"), r#""/v1""#).unwrap();
+        fs::write(
+            &file,
+            configuration("/v1").replace(r#""/v1""#, "./path.dhall"),
+        )
+        .unwrap();
+
+        assert!(watched_files(&name).contains(&dir.join("path.dhall")));
+
+        let state = Arc::new(RwLock::new(State {
+            sources: vec![name.clone()],
+            ..State::default()
+        }));
+        let watch = ConfigurationWatch::new(name.clone());
+        let configuration = load_dhall_expectation_file(name.clone()).await.unwrap();
+        add_expectations_in_state(state.clone(), configuration)
+            .await
+            .unwrap();
+        tokio::spawn(watch.watch(state.clone(), Duration::from_millis(50)));
+        assert_eq!(vec![r#"Some(Exact("/v1"))"#], paths(&state));
+
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+        fs::write(dir.join("path.dhall"), r#""/v2""#).unwrap();
+        for _ in 0..300 {
+            if paths(&state) != vec![r#"Some(Exact("/v1"))"#] {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+        assert_eq!(vec![r#"Some(Exact("/v2"))"#], paths(&state));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_changed_configuration() {
+        let dir = std::env::temp_dir().join(format!("dhall-mock-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("watched.dhall");
+        let name = file.to_string_lossy().to_string();
+        fs::write(&file, configuration("/v1")).unwrap();
+
+        let state = Arc::new(RwLock::new(State {
+            sources: vec![name.clone()],
+            ..State::default()
+        }));
+        let configuration_v1 = load_dhall_expectation(name.clone(), configuration("/v1"))
+            .await
+            .unwrap();
+        add_expectations_in_state(state.clone(), configuration_v1)
+            .await
+            .unwrap();
+        tokio::spawn(watch_configuration_file(
+            state.clone(),
+            name.clone(),
+            Duration::from_millis(50),
+        ));
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+
+        fs::write(&file, "[ 42 ]").unwrap();
+        tokio::time::delay_for(Duration::from_secs(2)).await;
+        assert_eq!(vec![r#"Some(Exact("/v1"))"#], paths(&state));
+
+        fs::write(&file, configuration("/v2")).unwrap();
+        for _ in 0..300 {
+            if paths(&state) != vec![r#"Some(Exact("/v1"))"#] {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+        assert_eq!(vec![r#"Some(Exact("/v2"))"#], paths(&state));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_failed_configuration_in_load_order() {
+        let state = Arc::new(RwLock::new(State {
+            sources: vec!["first.dhall".to_string(), "second.dhall".to_string()],
+            ..State::default()
+        }));
+        let second = load_dhall_expectation("second.dhall".to_string(), configuration("/second"))
+            .await
+            .unwrap();
+        add_expectations_in_state(state.clone(), second)
+            .await
+            .unwrap();
+
+        let first = load_dhall_expectation("first.dhall".to_string(), configuration("/first"))
+            .await
+            .unwrap();
+        assert!(replace_source_expectations_in_state(state.clone(), first)
+            .await
+            .unwrap());
+
+        assert_eq!(
+            vec![r#"Some(Exact("/first"))"#, r#"Some(Exact("/second"))"#],
+            paths(&state)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replaced_expectations_detach_configuration() {
+        let state = Arc::new(RwLock::new(State {
+            sources: vec!["first.dhall".to_string()],
+            ..State::default()
+        }));
+        let first = load_dhall_expectation("first.dhall".to_string(), configuration("/first"))
+            .await
+            .unwrap();
+        add_expectations_in_state(state.clone(), first)
+            .await
+            .unwrap();
+        let admin = load_dhall_expectation("admin".to_string(), configuration("/admin"))
+            .await
+            .unwrap();
+        replace_expectations_in_state(state.clone(), admin)
+            .await
+            .unwrap();
+        assert!(state.read().unwrap().sources.is_empty());
+
+        let first = load_dhall_expectation("first.dhall".to_string(), configuration("/reloaded"))
+            .await
+            .unwrap();
+        assert!(!replace_source_expectations_in_state(state.clone(), first)
+            .await
+            .unwrap());
+
+        assert_eq!(vec![r#"Some(Exact("/admin"))"#], paths(&state));
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sxd_document::dom::{ChildOfElement, ChildOfRoot, Element};
+use sxd_document::parser;
+use sxd_xpath::{Context, Factory, Value};
+
+use crate::mock::model::StringMatcher;
+use crate::mock::serde as serde_mock;
+
+type ExpandedName = (Option<String>, String);
+
+/// Xml element compared structurally : names are compared with their namespace uri (not the prefix),
+/// attributes order, comments and whitespace around texts are ignored.
+#[derive(Debug, PartialEq, Clone)]
+struct XmlElement {
+    name: ExpandedName,
+    attributes: BTreeMap<ExpandedName, String>,
+    children: Vec<XmlNode>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum XmlNode {
+    Element(XmlElement),
+    Text(String),
+}
+
+impl XmlElement {
+    fn from_dom(element: Element) -> XmlElement {
+        let name = element.name();
+        let attributes = element
+            .attributes()
+            .iter()
+            .map(|attribute| {
+                let name = attribute.name();
+                (
+                    (
+                        name.namespace_uri().map(String::from),
+                        name.local_part().to_string(),
+                    ),
+                    attribute.value().to_string(),
+                )
+            })
+            .collect();
+
+        let mut children: Vec<XmlNode> = vec![];
+        for child in element.children() {
+            match child {
+                ChildOfElement::Element(element) => {
+                    children.push(XmlNode::Element(XmlElement::from_dom(element)))
+                }
+                ChildOfElement::Text(text) => {
+                    // Adjacent texts (ex: split by a comment) are merged before trimming
+                    if let Some(XmlNode::Text(previous)) = children.last_mut() {
+                        previous.push_str(text.text());
+                    } else {
+                        children.push(XmlNode::Text(text.text().to_string()));
+                    }
+                }
+                _ => (),
+            }
+        }
+        let children = children
+            .into_iter()
+            .filter_map(|child| match child {
+                XmlNode::Text(text) if text.trim().is_empty() => None,
+                XmlNode::Text(text) => Some(XmlNode::Text(text.trim().to_string())),
+                element => Some(element),
+            })
+            .collect();
+
+        XmlElement {
+            name: (
+                name.namespace_uri().map(String::from),
+                name.local_part().to_string(),
+            ),
+            attributes,
+            children,
+        }
+    }
+}
+
+fn parse_root_element(xml: &str) -> Result<XmlElement, String> {
+    let package = parser::parse(xml).map_err(|e| format!("Invalid xml document : {}", e))?;
+    let document = package.as_document();
+    document
+        .root()
+        .children()
+        .into_iter()
+        .find_map(|child| match child {
+            ChildOfRoot::Element(element) => Some(XmlElement::from_dom(element)),
+            _ => None,
+        })
+        .ok_or_else(|| String::from("Xml document without root element"))
+}
+
+/// Xml document parsed at configuration load.
+#[derive(Debug, Clone)]
+pub struct XmlDocument {
+    source: String,
+    root: XmlElement,
+}
+
+impl XmlDocument {
+    /// Test if `xml` is structurally equal to this document.
+    pub fn matches(&self, xml: &str) -> bool {
+        parse_root_element(xml)
+            .map(|root| root == self.root)
+            .unwrap_or(false)
+    }
+}
+
+impl PartialEq for XmlDocument {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+    }
+}
+
+impl FromStr for XmlDocument {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        Ok(XmlDocument {
+            source: source.to_string(),
+            root: parse_root_element(source)?,
+        })
+    }
+}
+
+impl fmt::Display for XmlDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// XPath expression validated at configuration load.
+/// Compiled expressions can't be shared between threads, they are built again for each evaluation.
+#[derive(Debug, Clone)]
+pub struct XPathExpression {
+    source: String,
+    /// Namespace prefixes used by the expression, evaluating it with an undeclared prefix panics.
+    prefixes: Vec<String>,
+}
+
+impl XPathExpression {
+    /// Collect `prefix` of the `prefix:name` qualified names, outside of string literals and axes (`child::`).
+    fn used_prefixes(source: &str) -> Vec<String> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut prefixes: Vec<String> = vec![];
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            if c == '"' || c == '\'' {
+                index += chars[index + 1..]
+                    .iter()
+                    .position(|&end| end == c)
+                    .map(|position| position + 2)
+                    .unwrap_or(chars.len() - index);
+            } else if c.is_alphabetic() || c == '_' {
+                let start = index;
+                while index < chars.len()
+                    && (chars[index].is_alphanumeric() || "_-.".contains(chars[index]))
+                {
+                    index += 1;
+                }
+                let qualified = chars.get(index) == Some(&':')
+                    && chars.get(index + 1).map(|&c| c != ':').unwrap_or(false);
+                let prefix: String = chars[start..index].iter().collect();
+                if qualified && !prefixes.contains(&prefix) {
+                    prefixes.push(prefix);
+                }
+            } else {
+                index += 1;
+            }
+        }
+        prefixes
+    }
+}
+
+impl PartialEq for XPathExpression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for XPathExpression {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match Factory::new().build(source) {
+            Ok(Some(_)) => Ok(XPathExpression {
+                source: source.to_string(),
+                prefixes: XPathExpression::used_prefixes(source),
+            }),
+            Ok(None) => Err(format!("Empty XPath {}", source)),
+            Err(e) => Err(format!("Invalid XPath {} : {}", source, e)),
+        }
+    }
+}
+
+impl fmt::Display for XPathExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum XPathMatcher {
+    Exists,
+    Absent,
+    Matches(StringMatcher),
+}
+
+/// Assertion on the values selected by an XPath expression, satisfied if any selected value
+/// matches (`Absent` is satisfied if nothing is selected).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct XPathPredicate {
+    #[serde(with = "serde_mock::parsed_string")]
+    pub path: XPathExpression,
+    pub matcher: XPathMatcher,
+}
+
+/// XPath body assertions, the expressions only use the declared namespace prefixes.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(try_from = "XPathBodyDefinition")]
+pub struct XPathBody {
+    pub namespaces: Vec<(String, String)>,
+    pub predicates: Vec<XPathPredicate>,
+}
+
+#[derive(Deserialize)]
+struct XPathBodyDefinition {
+    namespaces: Vec<(String, String)>,
+    predicates: Vec<XPathPredicate>,
+}
+
+impl TryFrom<XPathBodyDefinition> for XPathBody {
+    type Error = String;
+
+    fn try_from(definition: XPathBodyDefinition) -> Result<Self, Self::Error> {
+        for predicate in definition.predicates.iter() {
+            let undeclared = predicate.path.prefixes.iter().find(|used| {
+                !definition
+                    .namespaces
+                    .iter()
+                    .any(|(prefix, _)| prefix == *used)
+            });
+            if let Some(prefix) = undeclared {
+                return Err(format!(
+                    "Undeclared namespace prefix {} in XPath {}",
+                    prefix, predicate.path
+                ));
+            }
+        }
+        Ok(XPathBody {
+            namespaces: definition.namespaces,
+            predicates: definition.predicates,
+        })
+    }
+}
+
+impl XPathBody {
+    pub fn matches(&self, xml: &str) -> bool {
+        test_xpath(xml, &self.namespaces, &self.predicates)
+    }
+}
+
+/// Test all the `predicates` on the `xml` document, namespaces are `(prefix, uri)` couples usable in the expressions.
+pub fn test_xpath(
+    xml: &str,
+    namespaces: &[(String, String)],
+    predicates: &[XPathPredicate],
+) -> bool {
+    let package = match parser::parse(xml) {
+        Ok(package) => package,
+        Err(_) => return false,
+    };
+    let document = package.as_document();
+    let mut context = Context::new();
+    for (prefix, uri) in namespaces.iter() {
+        context.set_namespace(prefix, uri);
+    }
+    let factory = Factory::new();
+
+    predicates.iter().all(|predicate| {
+        let declared = predicate
+            .path
+            .prefixes
+            .iter()
+            .all(|used| namespaces.iter().any(|(prefix, _)| prefix == used));
+        if !declared {
+            return false;
+        }
+        let values = match factory.build(&predicate.path.source) {
+            Ok(Some(xpath)) => match xpath.evaluate(&context, document.root()) {
+                Ok(value) => selected_values(value),
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+        match &predicate.matcher {
+            XPathMatcher::Exists => !values.is_empty(),
+            XPathMatcher::Absent => values.is_empty(),
+            XPathMatcher::Matches(matcher) => values.iter().any(|value| matcher.matches(value)),
+        }
+    })
+}
+
+/// Text values selected by an xpath : string value of each node, a `false` boolean selects nothing.
+fn selected_values(value: Value) -> Vec<String> {
+    match value {
+        Value::Nodeset(nodes) => nodes
+            .document_order()
+            .iter()
+            .map(|node| node.string_value())
+            .collect(),
+        Value::Boolean(false) => vec![],
+        value => vec![value.into_string()],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_structural_equality() {
+        let expected: XmlDocument =
+            r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+                <soap:Body>
+                    <GetUser id="42" version="2">
+                        <Name>robert</Name>
+                    </GetUser>
+                </soap:Body>
+            </soap:Envelope>"#
+                .parse()
+                .unwrap();
+
+        assert!(expected.matches(
+            r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><GetUser version="2" id="42"><!-- user --><Name> robert </Name></GetUser></s:Body></s:Envelope>"#
+        ));
+        assert!(!expected.matches(
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><GetUser version="2" id="43"><Name>robert</Name></GetUser></s:Body></s:Envelope>"#
+        ));
+        assert!(!expected.matches(
+            r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope"><s:Body><GetUser version="2" id="42"><Name>robert</Name></GetUser></s:Body></s:Envelope>"#
+        ));
+        assert!(!expected.matches("not xml"));
+    }
+
+    #[test]
+    fn test_used_prefixes() {
+        assert_eq!(
+            vec!["soap", "u"],
+            XPathExpression::used_prefixes(
+                "/soap:Envelope/child::soap:Body/u:User[@u:id = 'a:b' and @version=\"x:y\"]"
+            )
+        );
+        assert!(XPathExpression::used_prefixes("count(//User) > 1").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_documents() {
+        assert!("<a><b></a>".parse::<XmlDocument>().is_err());
+        assert!("//a[".parse::<XPathExpression>().is_err());
+    }
+
+    #[test]
+    fn test_xpath_body_fail_on_undeclared_prefix() {
+        let definition = |namespaces: Vec<(String, String)>| XPathBodyDefinition {
+            namespaces,
+            predicates: vec![XPathPredicate {
+                path: "/soap:Envelope//u:GetUser".parse().unwrap(),
+                matcher: XPathMatcher::Exists,
+            }],
+        };
+        let soap = (
+            String::from("soap"),
+            String::from("http://schemas.xmlsoap.org/soap/envelope/"),
+        );
+
+        assert_eq!(
+            Err(String::from(
+                "Undeclared namespace prefix u in XPath /soap:Envelope//u:GetUser"
+            )),
+            XPathBody::try_from(definition(vec![soap.clone()]))
+        );
+        assert!(XPathBody::try_from(definition(vec![
+            soap,
+            (String::from("u"), String::from("urn:users"))
+        ]))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_xpath_predicates() {
+        let xml = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/" xmlns:u="urn:users">
+                <soap:Body>
+                    <u:GetUser id="42"><u:Name>robert</u:Name></u:GetUser>
+                </soap:Body>
+            </soap:Envelope>"#;
+        let namespaces = vec![
+            (
+                String::from("soap"),
+                String::from("http://schemas.xmlsoap.org/soap/envelope/"),
+            ),
+            (String::from("users"), String::from("urn:users")),
+        ];
+        let predicate = |path: &str, matcher: XPathMatcher| XPathPredicate {
+            path: path.parse().unwrap(),
+            matcher,
+        };
+
+        assert!(test_xpath(
+            xml,
+            &namespaces,
+            &[
+                predicate(
+                    "/soap:Envelope/soap:Body/users:GetUser",
+                    XPathMatcher::Exists
+                ),
+                predicate(
+                    "//users:GetUser/@id",
+                    XPathMatcher::Matches(StringMatcher::Exact(String::from("42")))
+                ),
+                predicate(
+                    "//users:Name",
+                    XPathMatcher::Matches(StringMatcher::StartsWith(String::from("rob")))
+                ),
+                predicate("//users:DeleteUser", XPathMatcher::Absent),
+                predicate("count(//users:Name) = 1", XPathMatcher::Exists),
+            ]
+        ));
+        assert!(!test_xpath(
+            xml,
+            &namespaces,
+            &[predicate(
+                "//users:GetUser/@id",
+                XPathMatcher::Matches(StringMatcher::Exact(String::from("43")))
+            )]
+        ));
+        assert!(!test_xpath(
+            xml,
+            &namespaces,
+            &[predicate("count(//users:Name) = 2", XPathMatcher::Exists)]
+        ));
+        assert!(!test_xpath(
+            xml,
+            &[],
+            &[predicate("//users:Name", XPathMatcher::Exists)]
+        ));
+    }
+}
@@ -1,12 +1,19 @@
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use log::{debug, info};
+use percent_encoding::percent_decode_str;
 
 use anyhow::{anyhow, Context, Error};
 
 use super::not_found_response;
+use crate::mock::journal::JournalFilter;
 use crate::mock::service::SharedState;
-use crate::mock::service::{add_expectations_in_state, load_dhall_expectation};
+use crate::mock::service::{
+    add_expectations_in_state, clear_expectations_in_state, load_dhall_expectation,
+    load_dhall_verification, remove_expectation_from_state, replace_expectations_in_state,
+    reset_scenario_in_state,
+};
+use crate::mock::verification::Verification;
 use crate::web::utils;
 use bytes::buf::BufExt;
 use futures::TryFutureExt;
@@ -15,12 +22,19 @@ use std::io::Read;
 pub struct AdminServerContext {
     pub http_bind: String,
     pub state: SharedState,
+    /// PEM certificate served by the mock server when it uses https
+    pub tls_certificate: Option<String>,
 }
 
 pub(crate) async fn server(context: AdminServerContext) -> Result<(), Error> {
-    let AdminServerContext { http_bind, state } = context;
+    let AdminServerContext {
+        http_bind,
+        state,
+        tls_certificate,
+    } = context;
     let make_svc = make_service_fn(move |_| {
         let state = state.clone();
+        let tls_certificate = tls_certificate.clone();
         async {
             Ok::<_, Error>(service_fn(move |req| {
                 debug!(
@@ -28,7 +42,7 @@ pub(crate) async fn server(context: AdminServerContext) -> Result<(), Error> {
                     req.method(),
                     req.uri().path()
                 );
-                handler(req, state.clone())
+                handler(req, state.clone(), tls_certificate.clone())
             }))
         }
     });
@@ -44,12 +58,24 @@ pub(crate) async fn server(context: AdminServerContext) -> Result<(), Error> {
     server.await.context("Error on admin server execution")
 }
 
-async fn handler(req: Request<hyper::Body>, state: SharedState) -> Result<Response<Body>, Error> {
+async fn handler(
+    req: Request<hyper::Body>,
+    state: SharedState,
+    tls_certificate: Option<String>,
+) -> Result<Response<Body>, Error> {
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/health") => Response::builder()
             .status(StatusCode::OK)
             .body(Body::empty())
             .map_err(|_| anyhow!("Something bad happened.")),
+        (&Method::GET, "/tls/certificate") => match tls_certificate {
+            Some(certificate) => Response::builder()
+                .status(StatusCode::OK)
+                .header(hyper::header::CONTENT_TYPE, "application/x-pem-file")
+                .body(Body::from(certificate))
+                .map_err(|_| anyhow!("Something bad happened.")),
+            None => not_found_response(),
+        },
         (&Method::GET, "/expectations") => {
             let read_state = state
                 .read()
@@ -69,7 +95,7 @@ async fn handler(req: Request<hyper::Body>, state: SharedState) -> Result<Respon
                 .read_to_string(&mut read_body)?;
 
             match load_dhall_expectation("POST web configuration".to_string(), read_body)
-                .and_then(|expectations| add_expectations_in_state(state, expectations))
+                .and_then(|configuration| add_expectations_in_state(state, configuration))
                 .await
             {
                 Ok(()) => Response::builder()
@@ -82,6 +108,164 @@ async fn handler(req: Request<hyper::Body>, state: SharedState) -> Result<Respon
                     .map_err(|_| anyhow!("Something bad happened.")),
             }
         }
+        (&Method::PUT, "/expectations") => {
+            let mut read_body = String::new();
+            hyper::body::aggregate(req)
+                .await?
+                .reader()
+                .read_to_string(&mut read_body)?;
+
+            match load_dhall_expectation("PUT web configuration".to_string(), read_body)
+                .and_then(|configuration| replace_expectations_in_state(state, configuration))
+                .await
+            {
+                Ok(()) => Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::empty())
+                    .map_err(|_| anyhow!("Something bad happened.")),
+                Err(e) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("{:#}", e)))
+                    .map_err(|_| anyhow!("Something bad happened.")),
+            }
+        }
+        (&Method::DELETE, "/expectations") => {
+            clear_expectations_in_state(state).await?;
+
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .map_err(|_| anyhow!("Something bad happened."))
+        }
+        (&Method::DELETE, path) if path.starts_with("/expectations/") => {
+            let id = match path["/expectations/".len()..].parse::<u64>() {
+                Ok(id) => id,
+                Err(_) => return not_found_response(),
+            };
+            if remove_expectation_from_state(state, id).await? {
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty())
+                    .map_err(|_| anyhow!("Something bad happened."))
+            } else {
+                not_found_response()
+            }
+        }
+        (&Method::GET, "/requests") => {
+            let filter = match JournalFilter::from_query(req.uri().query().unwrap_or("")) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    return Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("{:#}", e)))
+                        .map_err(|_| anyhow!("Something bad happened."))
+                }
+            };
+            let read_state = state
+                .read()
+                .map_err(|e| anyhow!("Error acquiring lock on state : {}", e))?;
+
+            let body = serde_json::to_string(&read_state.journal()?.find(&filter))?;
+            Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from(body))
+                .map_err(|_| anyhow!("Something bad happened."))
+        }
+        (&Method::DELETE, "/requests") => {
+            state
+                .read()
+                .map_err(|e| anyhow!("Error acquiring lock on state : {}", e))?
+                .journal()?
+                .clear();
+
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .map_err(|_| anyhow!("Something bad happened."))
+        }
+        (&Method::POST, "/verify") => {
+            let json = req
+                .headers()
+                .get(hyper::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.starts_with("application/json"))
+                .unwrap_or(false);
+            let mut read_body = String::new();
+            hyper::body::aggregate(req)
+                .await?
+                .reader()
+                .read_to_string(&mut read_body)?;
+
+            let verification = if json {
+                serde_json::from_str::<Verification>(&read_body)
+                    .context("Error parsing json verification")
+            } else {
+                load_dhall_verification("POST web verification".to_string(), read_body).await
+            };
+            let verification = match verification {
+                Ok(verification) => verification,
+                Err(e) => {
+                    return Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from(format!("{:#}", e)))
+                        .map_err(|_| anyhow!("Something bad happened."))
+                }
+            };
+            let read_state = state
+                .read()
+                .map_err(|e| anyhow!("Error acquiring lock on state : {}", e))?;
+
+            let journal = read_state.journal()?;
+            let report = verification.verify(&journal);
+            let status = if report.passed {
+                StatusCode::OK
+            } else {
+                StatusCode::EXPECTATION_FAILED
+            };
+            Response::builder()
+                .status(status)
+                .body(Body::from(serde_json::to_string(&report)?))
+                .map_err(|_| anyhow!("Something bad happened."))
+        }
+        (&Method::GET, "/scenarios") => {
+            let read_state = state
+                .read()
+                .map_err(|e| anyhow!("Error acquiring lock on state : {}", e))?;
+
+            let body =
+                serde_json::to_string(&read_state.scenarios.states(&read_state.expectations))?;
+            Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from(body))
+                .map_err(|_| anyhow!("Something bad happened."))
+        }
+        (&Method::DELETE, "/scenarios") => {
+            state
+                .write()
+                .map_err(|e| anyhow!("Error acquiring lock on state : {}", e))?
+                .scenarios
+                .reset();
+
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .map_err(|_| anyhow!("Something bad happened."))
+        }
+        (&Method::DELETE, path) if path.starts_with("/scenarios/") => {
+            // No scenario can be named with an invalid UTF-8 name
+            let reset = match percent_decode_str(&path["/scenarios/".len()..]).decode_utf8() {
+                Ok(name) => reset_scenario_in_state(state, &name).await?,
+                Err(_) => false,
+            };
+            if reset {
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty())
+                    .map_err(|_| anyhow!("Something bad happened."))
+            } else {
+                not_found_response()
+            }
+        }
         _ => not_found_response(),
     }
 }
@@ -0,0 +1,612 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::mock::model::{ClientCertificate, Fault};
+
+/// Internal header used by the mock service to ask the connection to write a custom status reason.
+/// Hyper always writes the canonical reason phrase, so the status line is patched on its way out.
+pub(crate) const STATUS_REASON_HEADER: &str = "x-dhall-mock-status-reason";
+
+/// Internal header used by the mock service to ask the connection to fail instead of sending the response.
+pub(crate) const FAULT_HEADER: &str = "x-dhall-mock-fault";
+
+/// Internal header marking the response to a `HEAD` request : hyper doesn't write its body
+/// even when it has a content length.
+pub(crate) const HEAD_RESPONSE_HEADER: &str = "x-dhall-mock-head-response";
+
+/// Bytes sent instead of a response by `Fault::MalformedResponse`.
+const GARBAGE: &[u8] = b"\x00\xffDHALL-MOCK \x7f\x1b[garbage]\xfe\r\n\r\n";
+
+/// Wraps a client connection of the mock server to alter the raw bytes written by hyper.
+pub(crate) struct MockConnection<S> {
+    inner: S,
+    responses: ResponseStream,
+    pending: Vec<u8>,
+    written: usize,
+    fault: Option<FaultState>,
+}
+
+/// Progress of a fault once its response has been written by hyper.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum FaultState {
+    /// Number of body bytes still sent before resetting the connection
+    Truncating(usize),
+    /// Close the connection once the pending bytes are written, with a reset instead of a graceful close
+    Closing { reset: bool },
+    /// Swallow all the following responses while keeping the connection open
+    Stalled,
+}
+
+/// Fault asked by a response head.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct HeadFault {
+    fault: Fault,
+    /// End of the response head in the rewritten bytes
+    head_end: usize,
+    /// Body length announced by the response head, 0 when unknown
+    content_length: usize,
+}
+
+impl<S> MockConnection<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        MockConnection {
+            inner,
+            responses: ResponseStream::default(),
+            pending: vec![],
+            written: 0,
+            fault: None,
+        }
+    }
+
+    /// Start the fault asked by the response head in `buf`, the bytes to send are left pending.
+    fn start_fault(&mut self, head_fault: HeadFault, mut buf: Vec<u8>) {
+        let (pending, state) = match head_fault.fault {
+            Fault::ConnectionReset => (vec![], FaultState::Closing { reset: true }),
+            Fault::EmptyResponse => (vec![], FaultState::Closing { reset: false }),
+            Fault::MalformedResponse => (GARBAGE.to_vec(), FaultState::Closing { reset: false }),
+            Fault::Stall => (vec![], FaultState::Stalled),
+            Fault::TruncatedBody => {
+                let kept = head_fault.content_length / 2;
+                let sent = kept.min(buf.len() - head_fault.head_end);
+                buf.truncate(head_fault.head_end + sent);
+                (buf, truncating(kept - sent))
+            }
+        };
+        self.pending = pending;
+        self.fault = Some(state);
+    }
+}
+
+impl<S: ClientIdentity> MockConnection<S> {
+    pub(crate) fn client_certificate(&self) -> Option<ClientCertificate> {
+        self.inner.client_certificate()
+    }
+}
+
+/// Identity proven by the client of a connection.
+pub(crate) trait ClientIdentity {
+    fn client_certificate(&self) -> Option<ClientCertificate>;
+}
+
+impl ClientIdentity for TcpStream {
+    fn client_certificate(&self) -> Option<ClientCertificate> {
+        None
+    }
+}
+
+/// Socket of a connection, reset by the faults that abort it.
+pub(crate) trait ResetOnClose {
+    /// Send a reset instead of a graceful close when the connection is dropped.
+    fn reset_on_close(&self) -> io::Result<()>;
+}
+
+impl ResetOnClose for TcpStream {
+    fn reset_on_close(&self) -> io::Result<()> {
+        self.set_linger(Some(Duration::from_secs(0)))
+    }
+}
+
+/// Value of the fault header asking the connection for `fault`.
+pub(crate) fn fault_header(fault: Fault) -> &'static str {
+    match fault {
+        Fault::ConnectionReset => "connection-reset",
+        Fault::TruncatedBody => "truncated-body",
+        Fault::MalformedResponse => "malformed-response",
+        Fault::EmptyResponse => "empty-response",
+        Fault::Stall => "stall",
+    }
+}
+
+fn parse_fault(value: &[u8]) -> Option<Fault> {
+    [
+        Fault::ConnectionReset,
+        Fault::TruncatedBody,
+        Fault::MalformedResponse,
+        Fault::EmptyResponse,
+        Fault::Stall,
+    ]
+    .iter()
+    .copied()
+    .find(|fault| fault_header(*fault).as_bytes() == value)
+}
+
+impl<S: AsyncWrite + ResetOnClose + Unpin> MockConnection<S> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write rewritten response",
+                    )))
+                }
+                Poll::Ready(Ok(n)) => self.written += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Apply the fault in progress to `buf`, `None` when there is no fault.
+    fn poll_write_fault(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Option<io::Result<usize>> {
+        match self.fault? {
+            FaultState::Stalled => return Some(Ok(buf.len())),
+            FaultState::Closing { reset } => return Some(Err(self.close(reset))),
+            FaultState::Truncating(remaining) => {
+                let sent = remaining.min(buf.len());
+                self.pending = buf[..sent].to_vec();
+                self.fault = Some(truncating(remaining - sent));
+            }
+        }
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Err(e)) => Some(Err(e)),
+            _ => Some(Ok(buf.len())),
+        }
+    }
+
+    /// Error returned to hyper so it drops the connection.
+    fn close(&mut self, reset: bool) -> io::Error {
+        if reset {
+            if let Err(e) = self.inner.reset_on_close() {
+                return e;
+            }
+        }
+        io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "connection closed by a mock fault",
+        )
+    }
+
+    fn poll_close_fault(&mut self) -> Poll<io::Result<()>> {
+        match self.fault {
+            Some(FaultState::Closing { reset }) => Poll::Ready(Err(self.close(reset))),
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+fn truncating(remaining: usize) -> FaultState {
+    if remaining == 0 {
+        FaultState::Closing { reset: true }
+    } else {
+        FaultState::Truncating(remaining)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MockConnection<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + ResetOnClose + Unpin> AsyncWrite for MockConnection<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            other => return other.map_ok(|_| 0),
+        }
+        if let Some(result) = self.poll_write_fault(cx, buf) {
+            return Poll::Ready(result);
+        }
+        let (rewritten, fault) = self.responses.rewrite(buf);
+        match fault {
+            Some(fault) => self.start_fault(fault, rewritten),
+            None => self.pending = rewritten,
+        }
+        if let Poll::Ready(Err(e)) = self.poll_write_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => match Pin::new(&mut self.inner).poll_flush(cx) {
+                Poll::Ready(Ok(())) => self.poll_close_fault(),
+                other => other,
+            },
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+/// Position in the responses written by hyper, internal headers are only looked for in response heads.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Framing {
+    /// The next bytes start a response head
+    Head,
+    /// Bytes left in a body with a content length
+    Body(usize),
+    /// Chunked body
+    Chunked(Chunk),
+    /// Body delimited by the end of the connection
+    UntilClose,
+}
+
+/// Position in a chunked body.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Chunk {
+    /// Hexadecimal size of the next chunk, read so far
+    Size(usize),
+    /// Rest of the size line (extensions) of a chunk
+    SizeLine(usize),
+    /// Bytes left in the chunk data, with its ending `\r\n`
+    Data(usize),
+    /// Length of the current trailer line after the last chunk
+    Trailers(usize),
+}
+
+/// Rewrites the responses written by hyper, response after response.
+#[derive(Debug)]
+struct ResponseStream {
+    framing: Framing,
+    /// Start of a response head not completely written yet
+    head: Vec<u8>,
+}
+
+impl Default for ResponseStream {
+    fn default() -> Self {
+        ResponseStream {
+            framing: Framing::Head,
+            head: vec![],
+        }
+    }
+}
+
+impl ResponseStream {
+    /// Bytes to send for `buf`, with the internal headers of the response heads applied.
+    /// Stops at the first response asking for a fault, the following bytes of `buf` are kept unchanged.
+    fn rewrite(&mut self, buf: &[u8]) -> (Vec<u8>, Option<HeadFault>) {
+        let mut result = Vec::with_capacity(buf.len());
+        let mut position = 0;
+        while position < buf.len() {
+            let rest = &buf[position..];
+            match self.framing {
+                Framing::Head => {
+                    let searched = self.head.len().saturating_sub(3);
+                    self.head.extend_from_slice(rest);
+                    let head_end = match find(&self.head, b"\r\n\r\n", searched) {
+                        Some(end) => end + 4,
+                        None => return (result, None),
+                    };
+                    let following = self.head.split_off(head_end);
+                    position = buf.len() - following.len();
+                    let head = parse_head(&std::mem::take(&mut self.head));
+                    result.extend_from_slice(&head.bytes);
+                    self.framing = head.body;
+                    if let Some(fault) = head.fault {
+                        let content_length = match head.body {
+                            Framing::Body(length) => length,
+                            _ => 0,
+                        };
+                        let head_end = result.len();
+                        result.extend_from_slice(&following);
+                        let head_fault = HeadFault {
+                            fault,
+                            head_end,
+                            content_length,
+                        };
+                        return (result, Some(head_fault));
+                    }
+                }
+                Framing::Body(remaining) => {
+                    let sent = remaining.min(rest.len());
+                    result.extend_from_slice(&rest[..sent]);
+                    position += sent;
+                    self.framing = if sent == remaining {
+                        Framing::Head
+                    } else {
+                        Framing::Body(remaining - sent)
+                    };
+                }
+                Framing::Chunked(Chunk::Data(remaining)) => {
+                    let sent = remaining.min(rest.len());
+                    result.extend_from_slice(&rest[..sent]);
+                    position += sent;
+                    self.framing = Framing::Chunked(if sent == remaining {
+                        Chunk::Size(0)
+                    } else {
+                        Chunk::Data(remaining - sent)
+                    });
+                }
+                Framing::Chunked(chunk) => {
+                    result.push(rest[0]);
+                    position += 1;
+                    self.framing = next_chunk(chunk, rest[0]);
+                }
+                Framing::UntilClose => {
+                    result.extend_from_slice(rest);
+                    position = buf.len();
+                }
+            }
+        }
+        (result, None)
+    }
+}
+
+/// Move in a chunked body outside of the chunk data with the next `byte`.
+fn next_chunk(chunk: Chunk, byte: u8) -> Framing {
+    let after_size = |size: usize| match size {
+        0 => Framing::Chunked(Chunk::Trailers(0)),
+        size => Framing::Chunked(Chunk::Data(size + 2)),
+    };
+    match chunk {
+        Chunk::Size(size) => match (byte as char).to_digit(16) {
+            Some(digit) => Framing::Chunked(Chunk::Size(size * 16 + digit as usize)),
+            None if byte == b'\n' => after_size(size),
+            None => Framing::Chunked(Chunk::SizeLine(size)),
+        },
+        Chunk::SizeLine(size) if byte == b'\n' => after_size(size),
+        Chunk::SizeLine(size) => Framing::Chunked(Chunk::SizeLine(size)),
+        // an empty line ends the trailers
+        Chunk::Trailers(length) if byte == b'\n' && length <= 1 => Framing::Head,
+        Chunk::Trailers(_) if byte == b'\n' => Framing::Chunked(Chunk::Trailers(0)),
+        Chunk::Trailers(length) => Framing::Chunked(Chunk::Trailers(length + 1)),
+        Chunk::Data(remaining) => Framing::Chunked(Chunk::Data(remaining)),
+    }
+}
+
+/// Response head without its internal headers.
+#[derive(Debug, PartialEq)]
+struct ResponseHead {
+    bytes: Vec<u8>,
+    fault: Option<Fault>,
+    /// Framing of the body following the head
+    body: Framing,
+}
+
+/// Apply and remove the internal headers of a complete response head, ending with `\r\n\r\n`.
+fn parse_head(head: &[u8]) -> ResponseHead {
+    let mut lines = head[..head.len() - 4].split(|b| *b == b'\n');
+    let status_line = lines.next().unwrap_or_default();
+    let status_line = status_line.strip_suffix(b"\r").unwrap_or(status_line);
+    // `HTTP/1.1 201 Created` -> `HTTP/1.1` and `201`
+    let mut status_parts = status_line.splitn(3, |b| *b == b' ');
+    let version = status_parts.next().unwrap_or_default();
+    let code = status_parts.next().unwrap_or_default();
+    let status = std::str::from_utf8(code)
+        .ok()
+        .and_then(|code| code.parse::<u16>().ok())
+        .unwrap_or(0);
+
+    let mut bytes = Vec::with_capacity(head.len());
+    let mut reason = None;
+    let mut fault = None;
+    let mut head_response = false;
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let separator = line.iter().position(|b| *b == b':').unwrap_or(line.len());
+        let name = String::from_utf8_lossy(&line[..separator]).to_ascii_lowercase();
+        let value = line.get(separator + 1..).unwrap_or_default();
+        let value = std::str::from_utf8(value).unwrap_or_default().trim();
+        match name.as_str() {
+            STATUS_REASON_HEADER => {
+                reason = Some(value.to_string());
+                continue;
+            }
+            FAULT_HEADER => {
+                fault = parse_fault(value.as_bytes());
+                continue;
+            }
+            HEAD_RESPONSE_HEADER => {
+                head_response = true;
+                continue;
+            }
+            "transfer-encoding" => chunked = value.to_ascii_lowercase().ends_with("chunked"),
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            _ => (),
+        }
+        bytes.extend_from_slice(line);
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes.extend_from_slice(b"\r\n");
+
+    let status_line = match reason {
+        Some(reason) => [version, b" ", code, b" ", reason.as_bytes()].concat(),
+        None => status_line.to_vec(),
+    };
+    let body = match content_length {
+        _ if head_response || status < 200 || status == 204 || status == 304 => Framing::Head,
+        _ if chunked => Framing::Chunked(Chunk::Size(0)),
+        Some(0) => Framing::Head,
+        Some(length) => Framing::Body(length),
+        None => Framing::UntilClose,
+    };
+    ResponseHead {
+        bytes: [status_line, b"\r\n".to_vec(), bytes].concat(),
+        fault,
+        body,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| index + from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rewrite(responses: &[&str]) -> String {
+        let mut stream = ResponseStream::default();
+        let rewritten: Vec<u8> = responses
+            .iter()
+            .flat_map(|buf| stream.rewrite(buf.as_bytes()).0)
+            .collect();
+        String::from_utf8(rewritten).unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_status_reason() {
+        let head = "HTTP/1.1 201 Created\r\nlocation: /users/1\r\nx-dhall-mock-status-reason: User created\r\ncontent-length: 0\r\n\r\n";
+
+        assert_eq!(
+            "HTTP/1.1 201 User created\r\nlocation: /users/1\r\ncontent-length: 0\r\n\r\n",
+            rewrite(&[head])
+        );
+    }
+
+    #[test]
+    fn test_rewrite_pipelined_status_reasons() {
+        let heads = "HTTP/1.1 200 OK\r\nx-dhall-mock-status-reason: Fine\r\ncontent-length: 2\r\n\r\nokHTTP/1.1 404 Not Found\r\nx-dhall-mock-status-reason: Nope\r\ncontent-length: 0\r\n\r\n";
+
+        assert_eq!(
+            "HTTP/1.1 200 Fine\r\ncontent-length: 2\r\n\r\nokHTTP/1.1 404 Nope\r\ncontent-length: 0\r\n\r\n",
+            rewrite(&[heads])
+        );
+    }
+
+    #[test]
+    fn test_rewrite_head_split_in_several_writes() {
+        assert_eq!(
+            "HTTP/1.1 200 Fine\r\ncontent-length: 2\r\n\r\nok",
+            rewrite(&[
+                "HTTP/1.1 200 OK\r\nx-dhall-mock-status",
+                "-reason: Fine\r\ncontent-length: 2\r",
+                "\n\r\nok"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_keep_internal_headers_in_bodies() {
+        let body = "\r\nx-dhall-mock-fault: stall\r\nx-dhall-mock-status-reason: Nope\r\n";
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        assert_eq!(response, rewrite(&[&response]));
+
+        let chunked = format!(
+            "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            body.len(),
+            body
+        );
+        let next =
+            "HTTP/1.1 200 OK\r\nx-dhall-mock-status-reason: Fine\r\ncontent-length: 0\r\n\r\n";
+
+        assert_eq!(
+            format!("{}HTTP/1.1 200 Fine\r\ncontent-length: 0\r\n\r\n", chunked),
+            rewrite(&[&chunked, next])
+        );
+    }
+
+    #[test]
+    fn test_response_to_head_request_has_no_body() {
+        let head =
+            "HTTP/1.1 200 OK\r\ncontent-length: 12\r\nx-dhall-mock-head-response: true\r\n\r\n";
+        let next =
+            "HTTP/1.1 200 OK\r\nx-dhall-mock-status-reason: Fine\r\ncontent-length: 0\r\n\r\n";
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\ncontent-length: 12\r\n\r\nHTTP/1.1 200 Fine\r\ncontent-length: 0\r\n\r\n",
+            rewrite(&[head, next])
+        );
+    }
+
+    #[test]
+    fn test_strip_fault() {
+        let head = "HTTP/1.1 200 OK\r\nx-dhall-mock-fault: truncated-body\r\ncontent-length: 10\r\n\r\n0123456789";
+
+        let (stripped, fault) = ResponseStream::default().rewrite(head.as_bytes());
+        let stripped_head = "HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n";
+
+        assert_eq!(
+            Some(HeadFault {
+                fault: Fault::TruncatedBody,
+                head_end: stripped_head.len(),
+                content_length: 10
+            }),
+            fault
+        );
+        assert_eq!(
+            format!("{}0123456789", stripped_head),
+            String::from_utf8(stripped).unwrap()
+        );
+        assert_eq!(
+            None,
+            ResponseStream::default()
+                .rewrite(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .1
+        );
+    }
+
+    #[test]
+    fn test_truncate_body() {
+        let mut connection = MockConnection::new(());
+        let head = "HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n";
+        let head_fault = HeadFault {
+            fault: Fault::TruncatedBody,
+            head_end: head.len(),
+            content_length: 10,
+        };
+
+        connection.start_fault(head_fault, format!("{}0123", head).into_bytes());
+
+        assert_eq!(format!("{}0123", head).as_bytes(), &connection.pending[..]);
+        assert_eq!(Some(FaultState::Truncating(1)), connection.fault);
+
+        connection.start_fault(head_fault, format!("{}0123456789", head).into_bytes());
+
+        assert_eq!(format!("{}01234", head).as_bytes(), &connection.pending[..]);
+        assert_eq!(Some(FaultState::Closing { reset: true }), connection.fault);
+    }
+
+    #[test]
+    fn test_keep_response_without_status_reason() {
+        let head = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+
+        assert_eq!(head, rewrite(&[head]));
+    }
+}
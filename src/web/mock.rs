@@ -1,31 +1,132 @@
 use std::convert::TryFrom;
 
 use anyhow::{anyhow, Context, Error};
+use futures::{Stream, TryStreamExt};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::server::accept;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server};
-use log::{debug, info};
+use log::{debug, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
 
-use crate::mock::model::{Expectation, HttpMethod, IncomingRequest, QueryParams};
-use crate::mock::service::search_for_mock;
+use crate::mock::delay::Delay;
+use crate::mock::model::{
+    ClientCertificate, Expectation, HttpMethod, HttpResponse, IncomingRequest, ProxyTarget,
+    QueryParams,
+};
 use crate::mock::service::SharedState;
+use crate::mock::service::{diagnose_request, record_request, search_for_mock};
+use crate::mock::template::TemplateContext;
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
 
-use super::not_found_response;
+use super::connection::{
+    fault_header, ClientIdentity, MockConnection, ResetOnClose, FAULT_HEADER, HEAD_RESPONSE_HEADER,
+    STATUS_REASON_HEADER,
+};
+use super::proxy::ProxyContext;
+use super::tls::TlsContext;
+use super::{internal_error_response, not_found_diagnostic_response, not_found_response};
 use crate::web::utils;
 
 pub struct MockServerContext {
     pub http_bind: String,
     pub state: SharedState,
+    /// explain in 404 responses why the closest expectations refused the request
+    pub diagnostics: bool,
+    /// serve https instead of http
+    pub tls: Option<TlsContext>,
+    /// forward the requests without matching expectation to this upstream
+    pub proxy_to: Option<ProxyTarget>,
 }
 
 pub(crate) async fn server(context: MockServerContext) -> Result<(), Error> {
-    let MockServerContext { http_bind, state } = context;
+    let MockServerContext {
+        http_bind,
+        state,
+        diagnostics,
+        tls,
+        proxy_to,
+    } = context;
+    let proxy = ProxyContext::new(proxy_to);
 
-    let make_svc = make_service_fn(move |_| {
+    let addr: SocketAddr = http_bind
+        .parse()
+        .context(format!("{} is not a valid ip config", http_bind))?;
+    let listener = TcpListener::bind(&addr)
+        .await
+        .context(format!("Error binding mock server on {}", addr))?;
+
+    match tls {
+        None => {
+            info!("Http server started on http://{}", addr);
+            serve(
+                listener.map_ok(MockConnection::new),
+                state,
+                diagnostics,
+                proxy,
+            )
+            .await
+        }
+        Some(tls) => {
+            info!("Http server started on https://{}", addr);
+            serve(tls_handshakes(listener, tls), state, diagnostics, proxy).await
+        }
+    }
+}
+
+/// Handshakes are made on their own task, a slow or failed handshake doesn't stop the
+/// server from accepting the other connections.
+fn tls_handshakes(
+    mut listener: TcpListener,
+    tls: TlsContext,
+) -> impl Stream<Item = Result<MockConnection<TlsStream<TcpStream>>, io::Error>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+            let acceptor = tls.acceptor();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let _ = sender.send(Ok(MockConnection::new(stream)));
+                    }
+                    Err(e) => warn!("Tls handshake failed : {}", e),
+                }
+            });
+        }
+    });
+    receiver
+}
+
+async fn serve<I, S>(
+    incoming: I,
+    state: SharedState,
+    diagnostics: bool,
+    proxy: ProxyContext,
+) -> Result<(), Error>
+where
+    I: Stream<Item = Result<MockConnection<S>, io::Error>>,
+    S: AsyncRead + AsyncWrite + ClientIdentity + ResetOnClose + Unpin + Send + 'static,
+{
+    let make_svc = make_service_fn(move |connection: &MockConnection<S>| {
         let state = state.clone();
-        async {
+        let proxy = proxy.clone();
+        let client_certificate = connection.client_certificate();
+        async move {
             // TODO add middleware for hyper server
             Ok::<_, Error>(service_fn(move |req| {
                 debug!(
@@ -33,23 +134,31 @@ pub(crate) async fn server(context: MockServerContext) -> Result<(), Error> {
                     req.method(),
                     req.uri().path()
                 );
-                handler(req, state.clone())
+                handler(
+                    req,
+                    state.clone(),
+                    diagnostics,
+                    client_certificate.clone(),
+                    proxy.clone(),
+                )
             }))
         }
     });
 
-    let addr = http_bind
-        .parse()
-        .context(format!("{} is not a valid ip config", http_bind))?;
-    let server = Server::bind(&addr)
+    Server::builder(accept::from_stream(incoming))
         .serve(make_svc)
-        .with_graceful_shutdown(utils::sigint(String::from("mock service")));
-
-    info!("Http server started on http://{}", addr);
-    server.await.context("Error on web server execution")
+        .with_graceful_shutdown(utils::sigint(String::from("mock service")))
+        .await
+        .context("Error on web server execution")
 }
 
-async fn handler(req: Request<Body>, state: SharedState) -> Result<Response<Body>, Error> {
+async fn handler(
+    req: Request<Body>,
+    state: SharedState,
+    diagnostics: bool,
+    client_certificate: Option<ClientCertificate>,
+    proxy: ProxyContext,
+) -> Result<Response<Body>, Error> {
     let (parts, body) = req.into_parts();
     let content = hyper::body::to_bytes(body).await?;
 
@@ -63,29 +172,122 @@ async fn handler(req: Request<Body>, state: SharedState) -> Result<Response<Body
         })
         .collect();
 
-    let params = url::form_urlencoded::parse(&parts.uri.to_string().as_bytes())
+    let params = url::form_urlencoded::parse(parts.uri.query().unwrap_or("").as_bytes())
         .into_owned()
         .fold(HashMap::new(), |mut acc: QueryParams, (k, v)| {
-            if let Some(set) = acc.get_mut(&k) {
-                set.insert(v);
-            } else {
-                acc.insert(k, HashSet::new());
-            }
+            acc.entry(k).or_insert_with(HashSet::new).insert(v);
             acc
         });
 
+    let (method, body) = match (
+        HttpMethod::try_from(parts.method.clone()),
+        String::from_utf8(content.to_vec()), //TODO read header to use the good string encoding
+    ) {
+        (Ok(method), Ok(body)) => (method, body),
+        // No expectation can match an extension method or a binary body, it's forwarded as is
+        _ => {
+            return match &proxy.fallback {
+                Some(fallback) => proxy.forward(&parts, content, fallback, &[]).await,
+                None => not_found_response(),
+            }
+        }
+    };
     let incoming_request = IncomingRequest {
-        method: HttpMethod::try_from(parts.method)?,
+        method,
         path: parts.uri.path().to_string(),
-        body: String::from_utf8(content.to_vec())?, //TODO read header to use the good string encoding
+        body,
         headers: map,
-        params: params,
+        params,
+        client_certificate,
     };
 
-    match search_for_mock(incoming_request, state).await? {
-        Some(expectation) => Response::try_from(expectation),
-        None => not_found_response(),
+    let expectation = search_for_mock(&incoming_request, state.clone()).await?;
+    let selected = expectation.as_ref().map(|entry| (entry, entry.response()));
+    let delay = selected
+        .and_then(|(_, response)| response.delay.as_ref())
+        .map(Delay::sample);
+    let mut response = match (selected, &proxy.fallback) {
+        (Some((entry, response)), _) => match response.proxy.as_ref() {
+            Some(upstream) => {
+                let proxied = proxy
+                    .forward(&parts, content, &upstream.target, &upstream.headers)
+                    .await?;
+                override_proxied_response(proxied, response).or_else(response_error)?
+            }
+            None => mock_response(&entry.expectation, response, &incoming_request)
+                .or_else(response_error)?,
+        },
+        (None, Some(fallback)) => proxy.forward(&parts, content, fallback, &[]).await?,
+        (None, None) if diagnostics => {
+            let closest = diagnose_request(&incoming_request, state.clone()).await?;
+            warn!(
+                "No expectation for {:?} {}, closest expectations mismatches : {}",
+                incoming_request.method,
+                incoming_request.path,
+                closest
+                    .iter()
+                    .map(|miss| format!("{:?}", miss.mismatches))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            not_found_diagnostic_response(&incoming_request, &closest)?
+        }
+        (None, None) => not_found_response()?,
+    };
+    record_request(
+        state,
+        incoming_request,
+        expectation.as_ref(),
+        response.status().as_u16(),
+    )
+    .await?;
+    if let Some(delay) = delay {
+        tokio::time::delay_for(delay).await;
+    }
+    if parts.method == Method::HEAD {
+        response
+            .headers_mut()
+            .insert(HEAD_RESPONSE_HEADER, HeaderValue::from_static("true"));
+    }
+    Ok(response)
+}
+
+/// Answer `500` when the response of an expectation can't be built, the request is still recorded.
+fn response_error(error: Error) -> Result<Response<Body>, Error> {
+    warn!("Error building mock response : {:#}", error);
+    internal_error_response(&error)
+}
+
+/// Replace the headers of a proxied response by the expectation response headers.
+fn override_proxied_response(
+    mut proxied: Response<Body>,
+    response: &HttpResponse,
+) -> Result<Response<Body>, Error> {
+    let headers = proxied.headers_mut();
+    for (name, _) in response.headers.iter() {
+        headers.remove(name.as_str());
+    }
+    for (name, value) in response.headers.iter() {
+        headers.append(
+            HeaderName::from_bytes(name.as_bytes())
+                .context(format!("Invalid header name {}", name))?,
+            HeaderValue::from_str(value).context(format!("Invalid header value {}", value))?,
+        );
+    }
+    if let Some(fault) = response.fault {
+        headers.insert(FAULT_HEADER, HeaderValue::from_static(fault_header(fault)));
     }
+    Ok(proxied)
+}
+
+/// Header value of a rendered header template, without the control characters (ex: line breaks)
+/// coming from the request.
+fn template_header_value(value: String) -> Result<HeaderValue, Error> {
+    let value: String = value
+        .chars()
+        .filter(|c| *c == '\t' || !c.is_control())
+        .collect();
+    HeaderValue::from_str(&value).context(format!("Invalid header value {}", value))
 }
 
 impl TryFrom<Method> for HttpMethod {
@@ -98,25 +300,46 @@ impl TryFrom<Method> for HttpMethod {
             Method::PUT => Ok(HttpMethod::PUT),
             Method::DELETE => Ok(HttpMethod::DELETE),
             Method::HEAD => Ok(HttpMethod::HEAD),
+            Method::PATCH => Ok(HttpMethod::PATCH),
+            Method::OPTIONS => Ok(HttpMethod::OPTIONS),
+            Method::TRACE => Ok(HttpMethod::TRACE),
+            Method::CONNECT => Ok(HttpMethod::CONNECT),
             method => Err(anyhow!("{} isn't managed as HttpMethod", method)),
         }
     }
 }
 
-impl TryFrom<Expectation> for Response<Body> {
-    type Error = anyhow::Error;
-
-    fn try_from(value: Expectation) -> Result<Response<Body>, Error> {
-        Response::builder()
-            .status(value.response.status_code.unwrap_or(200))
-            .body(
-                value
-                    .response
-                    .body
-                    .as_ref()
-                    .map(|body| Body::from(body.clone()))
-                    .unwrap_or(Body::empty()),
-            )
-            .context(format!("Error creating http response for {:?}", value))
+/// Response of `expectation`, its templates are rendered from `request`.
+fn mock_response(
+    expectation: &Expectation,
+    response: &HttpResponse,
+    request: &IncomingRequest,
+) -> Result<Response<Body>, Error> {
+    let context = TemplateContext::new(request, expectation.path_captures(request));
+    let mut builder = Response::builder().status(response.status_code.unwrap_or(200));
+    for (name, header_value) in response.headers.iter() {
+        builder = builder.header(name.as_str(), header_value.as_str());
+    }
+    for (name, template) in response.header_templates.iter() {
+        builder = builder.header(
+            name.as_str(),
+            template_header_value(template.render(&context))?,
+        );
+    }
+    if let Some(reason) = response.status_reason.as_ref() {
+        builder = builder.header(STATUS_REASON_HEADER, reason.as_str());
     }
+    if let Some(fault) = response.fault {
+        builder = builder.header(FAULT_HEADER, fault_header(fault));
+    }
+    let body = match (&response.body_template, &response.body_json, &response.body) {
+        (Some(template), _, _) => Body::from(template.render(&context)),
+        (None, Some(json), _) => Body::from(json.to_string()),
+        (None, None, Some(body)) => Body::from(body.clone()),
+        (None, None, None) => Body::empty(),
+    };
+    builder.body(body).context(format!(
+        "Error creating http response for {:?}",
+        expectation
+    ))
 }
@@ -1,8 +1,14 @@
 use anyhow::{anyhow, Error};
 use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+
+use crate::mock::model::{ExpectationEntry, ExpectationMiss, IncomingRequest};
 
 pub mod admin;
+mod connection;
 pub mod mock;
+mod proxy;
+pub mod tls;
 pub mod utils;
 
 fn not_found_response() -> Result<Response<Body>, Error> {
@@ -11,3 +17,26 @@ fn not_found_response() -> Result<Response<Body>, Error> {
         .body(Body::from("404 NotFound"))
         .map_err(|_| anyhow!("Error creating http not found response"))
 }
+
+fn internal_error_response(error: &Error) -> Result<Response<Body>, Error> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from(format!("500 InternalServerError : {:#}", error)))
+        .map_err(|_| anyhow!("Error creating http internal error response"))
+}
+
+fn not_found_diagnostic_response(
+    request: &IncomingRequest,
+    closest: &[ExpectationMiss<ExpectationEntry>],
+) -> Result<Response<Body>, Error> {
+    let body = json!({
+        "message": "No expectation matches the request",
+        "request": request,
+        "closest": closest,
+    });
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(|_| anyhow!("Error creating http not found response"))
+}
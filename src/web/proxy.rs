@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Context, Error};
+use bytes::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, VIA};
+use hyper::http::request::Parts;
+use hyper::{Body, Client, Request, Response, StatusCode};
+use hyper_rustls::HttpsConnector;
+use log::{info, warn};
+
+use super::connection::{FAULT_HEADER, HEAD_RESPONSE_HEADER, STATUS_REASON_HEADER};
+use crate::mock::model::ProxyTarget;
+
+/// Headers describing a single connection, they are not forwarded. `host` is set by the client
+/// from the upstream url, the internal headers of the mock connection are never taken from upstream.
+const HOP_BY_HOP_HEADERS: [&str; 12] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    STATUS_REASON_HEADER,
+    FAULT_HEADER,
+    HEAD_RESPONSE_HEADER,
+];
+
+/// Client forwarding the requests to the upstream servers.
+#[derive(Clone)]
+pub(crate) struct ProxyContext {
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// Upstream answering the requests without matching expectation
+    pub(crate) fallback: Option<ProxyTarget>,
+    /// `Via` entry added to the forwarded requests, a request already carrying it is a loop
+    via: String,
+}
+
+impl ProxyContext {
+    pub(crate) fn new(fallback: Option<ProxyTarget>) -> Self {
+        ProxyContext {
+            client: Client::builder().build(HttpsConnector::new()),
+            fallback,
+            via: format!("1.1 dhall-mock-{:08x}", rand::random::<u32>()),
+        }
+    }
+
+    fn is_loop(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(VIA)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|entry| entry.trim() == self.via)
+    }
+
+    /// Forward the request to `target`, the request path and query are appended to the target path.
+    /// `header_overrides` replace the request headers with the same name.
+    /// The upstream response body is streamed back, an unreachable upstream is answered with `502`.
+    pub(crate) async fn forward(
+        &self,
+        parts: &Parts,
+        body: Bytes,
+        target: &ProxyTarget,
+        header_overrides: &[(String, String)],
+    ) -> Result<Response<Body>, Error> {
+        let uri = target.upstream_uri(&parts.uri).map_err(|e| anyhow!(e))?;
+        if self.is_loop(&parts.headers) {
+            warn!(
+                "Proxy loop detected on {} {} to {}",
+                parts.method, parts.uri, uri
+            );
+            return Response::builder()
+                .status(StatusCode::LOOP_DETECTED)
+                .body(Body::from(format!("Proxy loop detected to {}", uri)))
+                .context("Error creating loop detected response");
+        }
+        let mut builder = Request::builder()
+            .method(parts.method.clone())
+            .uri(uri.clone());
+        for (name, value) in forwarded_headers(&parts.headers) {
+            if !header_overrides
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case(name.as_str()))
+            {
+                builder = builder.header(name, value);
+            }
+        }
+        for (name, value) in header_overrides.iter() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder = builder.header(VIA, self.via.as_str());
+        let request = builder
+            .body(Body::from(body))
+            .context(format!("Error creating proxy request to {}", uri))?;
+
+        match self.client.request(request).await {
+            Ok(response) => {
+                info!(
+                    "Forwarded {} {} to {} : {}",
+                    parts.method,
+                    parts.uri,
+                    uri,
+                    response.status()
+                );
+                let (mut head, body) = response.into_parts();
+                head.headers = forwarded_headers(&head.headers).collect();
+                Ok(Response::from_parts(head, body))
+            }
+            Err(e) => {
+                warn!(
+                    "Error forwarding {} {} to {} : {}",
+                    parts.method, parts.uri, uri, e
+                );
+                Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from(format!("Error forwarding to {} : {}", uri, e)))
+                    .context("Error creating bad gateway response")
+            }
+        }
+    }
+}
+
+/// Headers forwarded to the other side, without the hop-by-hop headers and the headers listed in
+/// the `Connection` header (RFC 7230 §6.1).
+fn forwarded_headers(headers: &HeaderMap) -> impl Iterator<Item = (HeaderName, HeaderValue)> + '_ {
+    let connection_headers: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    headers
+        .iter()
+        .filter(move |(name, _)| {
+            !HOP_BY_HOP_HEADERS.contains(&name.as_str())
+                && !connection_headers.iter().any(|n| n == name.as_str())
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_headers_skip_connection_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONNECTION, HeaderValue::from_static("close, X-Session"));
+        headers.insert("x-session", HeaderValue::from_static("secret"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("x-request-id", HeaderValue::from_static("42"));
+
+        let forwarded: Vec<(HeaderName, HeaderValue)> = forwarded_headers(&headers).collect();
+
+        assert_eq!(
+            vec![(
+                HeaderName::from_static("x-request-id"),
+                HeaderValue::from_static("42")
+            )],
+            forwarded
+        );
+    }
+
+    #[test]
+    fn test_detect_proxy_loop() {
+        let proxy = ProxyContext::new(None);
+        let mut headers = HeaderMap::new();
+        headers.insert(VIA, HeaderValue::from_static("1.1 gateway"));
+        assert!(!proxy.is_loop(&headers));
+
+        headers.insert(
+            VIA,
+            HeaderValue::from_str(&format!("1.1 gateway, {}", proxy.via)).unwrap(),
+        );
+        assert!(proxy.is_loop(&headers));
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Error};
+use rcgen::{CertificateParams, SanType};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+    RootCertStore, ServerConfig, Session,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use yasna::models::ObjectIdentifier;
+use yasna::{Tag, TagClass};
+
+use super::connection::{ClientIdentity, ResetOnClose};
+use crate::mock::model::ClientCertificate;
+
+/// Certificate and key used by the mock server to serve https.
+#[derive(Clone)]
+pub struct TlsContext {
+    /// PEM certificate chain, exposed by the admin server so clients can trust it
+    pub certificate: String,
+    config: Arc<ServerConfig>,
+}
+
+/// Client certificates requirement of a mutual tls connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientAuth {
+    /// Clients may connect without certificate
+    Optional,
+    Required,
+}
+
+impl FromStr for ClientAuth {
+    type Err = String;
+
+    fn from_str(client_auth: &str) -> Result<Self, Self::Err> {
+        match client_auth {
+            "optional" => Ok(ClientAuth::Optional),
+            "required" => Ok(ClientAuth::Required),
+            other => Err(format!(
+                "{} isn't a client auth mode, use optional or required",
+                other
+            )),
+        }
+    }
+}
+
+impl TlsContext {
+    pub fn from_pem_files(certificate_file: &str, key_file: &str) -> Result<TlsContext, Error> {
+        let certificate = fs::read_to_string(certificate_file).context(format!(
+            "Error reading tls certificate {}",
+            certificate_file
+        ))?;
+        let key =
+            fs::read_to_string(key_file).context(format!("Error reading tls key {}", key_file))?;
+        TlsContext::from_pem(certificate, &key)
+    }
+
+    /// Generate a self-signed certificate for `names`, either dns names or ip addresses.
+    pub fn self_signed(names: &[String]) -> Result<TlsContext, Error> {
+        let mut params = CertificateParams::new(vec![]);
+        params.subject_alt_names = names
+            .iter()
+            .map(|name| match name.parse::<IpAddr>() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) => SanType::DnsName(name.clone()),
+            })
+            .collect();
+        let certificate = rcgen::Certificate::from_params(params)
+            .map_err(|e| anyhow!("Error generating self-signed certificate : {}", e))?;
+        let certificate_pem = certificate
+            .serialize_pem()
+            .map_err(|e| anyhow!("Error serializing self-signed certificate : {}", e))?;
+        TlsContext::from_pem(certificate_pem, &certificate.serialize_private_key_pem())
+    }
+
+    pub fn from_pem(certificate: String, key: &str) -> Result<TlsContext, Error> {
+        let chain = certs(&mut BufReader::new(certificate.as_bytes()))
+            .map_err(|_| anyhow!("Invalid PEM tls certificate"))?;
+        if chain.is_empty() {
+            return Err(anyhow!("No certificate in tls certificate file"));
+        }
+        let mut keys = pkcs8_private_keys(&mut BufReader::new(key.as_bytes()))
+            .map_err(|_| anyhow!("Invalid PEM tls key"))?;
+        if keys.is_empty() {
+            keys = rsa_private_keys(&mut BufReader::new(key.as_bytes()))
+                .map_err(|_| anyhow!("Invalid PEM tls key"))?;
+        }
+        let key = keys
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No PKCS8 or RSA private key in tls key file"))?;
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(chain, key)
+            .context("Tls key doesn't match the certificate")?;
+        Ok(TlsContext {
+            certificate,
+            config: Arc::new(config),
+        })
+    }
+
+    /// Ask clients for a certificate signed by one of the `ca_bundle` PEM certificates.
+    pub fn with_client_ca(
+        mut self,
+        ca_bundle: &str,
+        client_auth: ClientAuth,
+    ) -> Result<TlsContext, Error> {
+        let mut roots = RootCertStore::empty();
+        let (valid, _) = roots
+            .add_pem_file(&mut BufReader::new(ca_bundle.as_bytes()))
+            .map_err(|_| anyhow!("Invalid PEM client CA bundle"))?;
+        if valid == 0 {
+            return Err(anyhow!("No valid certificate in client CA bundle"));
+        }
+        let verifier = match client_auth {
+            ClientAuth::Optional => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+            ClientAuth::Required => AllowAnyAuthenticatedClient::new(roots),
+        };
+        Arc::make_mut(&mut self.config).set_client_certificate_verifier(verifier);
+        Ok(self)
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config.clone())
+    }
+}
+
+impl ClientIdentity for TlsStream<TcpStream> {
+    fn client_certificate(&self) -> Option<ClientCertificate> {
+        let (_, session) = self.get_ref();
+        session
+            .get_peer_certificates()?
+            .first()
+            .and_then(|certificate| read_client_certificate(&certificate.0))
+    }
+}
+
+impl ResetOnClose for TlsStream<TcpStream> {
+    fn reset_on_close(&self) -> std::io::Result<()> {
+        self.get_ref().0.reset_on_close()
+    }
+}
+
+/// Subject and alternative names of a DER certificate.
+fn read_client_certificate(der: &[u8]) -> Option<ClientCertificate> {
+    let fields = yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let fields = reader.next().collect_sequence_of(|reader| {
+                let tag = reader.lookahead_tag()?;
+                Ok((tag, reader.read_der()?))
+            })?;
+            reader.next().read_der()?;
+            reader.next().read_der()?;
+            Ok(fields)
+        })
+    })
+    .ok()?;
+    // version [0], serial, signature, issuer, validity, subject, ..., extensions [3]
+    let subject = fields
+        .iter()
+        .filter(|(tag, _)| tag.tag_class == TagClass::Universal)
+        .nth(4)
+        .and_then(|(_, subject)| read_name(subject))?;
+    let subject_alt_names = fields
+        .iter()
+        .find(|(tag, _)| *tag == Tag::context(3))
+        .and_then(|(_, extensions)| read_subject_alt_names(extensions))
+        .unwrap_or_default();
+    Some(ClientCertificate {
+        subject,
+        subject_alt_names,
+    })
+}
+
+/// Distinguished name `C=FR, O=Acme, CN=partner-a` of a DER name, the attributes not encoded as
+/// text are skipped.
+fn read_name(der: &[u8]) -> Option<String> {
+    let attributes = yasna::parse_der(der, |reader| {
+        reader.collect_sequence_of(|reader| {
+            reader.collect_set_of(|reader| {
+                reader.read_sequence(|reader| {
+                    let oid = reader.next().read_oid()?;
+                    let value = reader.next().read_tagged_der()?;
+                    Ok((oid, value))
+                })
+            })
+        })
+    })
+    .ok()?;
+    Some(
+        attributes
+            .iter()
+            .flatten()
+            .filter_map(|(oid, value)| {
+                Some(format!("{}={}", attribute_short_name(oid), value.as_str()?))
+            })
+            .collect::<Vec<String>>()
+            .join(", "),
+    )
+}
+
+/// Short name of the usual distinguished name attributes, the dotted oid otherwise.
+fn attribute_short_name(oid: &ObjectIdentifier) -> String {
+    let name = match oid.components().as_slice() {
+        [2, 5, 4, 3] => "CN",
+        [2, 5, 4, 4] => "SN",
+        [2, 5, 4, 5] => "serialNumber",
+        [2, 5, 4, 6] => "C",
+        [2, 5, 4, 7] => "L",
+        [2, 5, 4, 8] => "ST",
+        [2, 5, 4, 9] => "street",
+        [2, 5, 4, 10] => "O",
+        [2, 5, 4, 11] => "OU",
+        [2, 5, 4, 12] => "title",
+        [2, 5, 4, 42] => "GN",
+        [1, 2, 840, 113549, 1, 9, 1] => "emailAddress",
+        [0, 9, 2342, 19200300, 100, 1, 1] => "UID",
+        [0, 9, 2342, 19200300, 100, 1, 25] => "DC",
+        _ => return oid.to_string(),
+    };
+    name.to_string()
+}
+
+/// Dns names, emails, uris and ip addresses of the subject alternative name extension in the DER
+/// `[3]` extensions of a certificate.
+fn read_subject_alt_names(der: &[u8]) -> Option<Vec<String>> {
+    let extensions = yasna::parse_der(der, |reader| {
+        reader.read_tagged(Tag::context(3), |reader| {
+            reader.collect_sequence_of(|reader| {
+                reader.read_sequence(|reader| {
+                    let oid = reader.next().read_oid()?;
+                    reader.read_optional(|reader| reader.read_bool())?;
+                    let value = reader.next().read_bytes()?;
+                    Ok((oid, value))
+                })
+            })
+        })
+    })
+    .ok()?;
+    let (_, names) = extensions
+        .iter()
+        .find(|(oid, _)| oid.components().as_slice() == [2, 5, 29, 17])?;
+    let names = yasna::parse_der(names, |reader| {
+        reader.collect_sequence_of(|reader| reader.read_tagged_der())
+    })
+    .ok()?;
+    Some(
+        names
+            .iter()
+            .filter(|name| name.tag().tag_class == TagClass::ContextSpecific)
+            .filter_map(|name| match name.tag().tag_number {
+                // rfc822Name, dNSName, uniformResourceIdentifier
+                1 | 2 | 6 => String::from_utf8(name.value().to_vec()).ok(),
+                // iPAddress
+                7 => ip_address(name.value()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+fn ip_address(bytes: &[u8]) -> Option<String> {
+    match bytes.len() {
+        4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets).to_string())
+        }
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::from(octets).to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_self_signed_certificate() {
+        let tls = TlsContext::self_signed(&[String::from("localhost"), String::from("127.0.0.1")])
+            .unwrap();
+
+        assert!(tls.certificate.starts_with("-----BEGIN CERTIFICATE-----"));
+    }
+
+    #[test]
+    fn test_read_client_certificate() {
+        let mut params = CertificateParams::new(vec![String::from("partner-a.example.com")]);
+        params
+            .subject_alt_names
+            .push(SanType::IpAddress("10.0.0.1".parse::<IpAddr>().unwrap()));
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Acme");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "partner-a");
+        let certificate = rcgen::Certificate::from_params(params).unwrap();
+
+        assert_eq!(
+            Some(ClientCertificate {
+                subject: String::from("O=Acme, CN=partner-a"),
+                subject_alt_names: vec![
+                    String::from("partner-a.example.com"),
+                    String::from("10.0.0.1")
+                ],
+            }),
+            read_client_certificate(&certificate.serialize_der().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_read_client_certificate_without_alt_names() {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CountryName, "FR");
+        let certificate = rcgen::Certificate::from_params(params).unwrap();
+
+        assert_eq!(
+            Some(ClientCertificate {
+                subject: String::from("C=FR"),
+                subject_alt_names: vec![],
+            }),
+            read_client_certificate(&certificate.serialize_der().unwrap())
+        );
+        assert_eq!(None, read_client_certificate(b"not a certificate"));
+    }
+
+    #[test]
+    fn test_refuse_invalid_client_ca() {
+        let tls = TlsContext::self_signed(&[String::from("localhost")]).unwrap();
+
+        assert!(tls
+            .with_client_ca("not a certificate", ClientAuth::Required)
+            .is_err());
+        assert!("sometimes".parse::<ClientAuth>().is_err());
+    }
+
+    #[test]
+    fn test_refuse_invalid_pem() {
+        assert!(TlsContext::from_pem(String::from("not a certificate"), "not a key").is_err());
+    }
+}
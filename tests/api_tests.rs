@@ -1,21 +1,28 @@
 extern crate dhall_mock;
 
-use std::collections::HashMap;
+use std::fs;
 use std::ops::DerefMut;
 use std::sync::Mutex;
 use std::sync::{Arc, RwLock};
-use std::{fs, panic};
+use std::time::{Duration, Instant};
 
 use lazy_static::lazy_static;
 use reqwest::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 
-use dhall_mock::mock::model::{Expectation, HttpMethod, HttpRequest, HttpResponse};
+use dhall_mock::mock::files::{configuration_files, load_configuration_files};
+use dhall_mock::mock::model::{
+    Expectation, HttpMethod, HttpRequest, HttpResponse, ProxyTarget, RequestPath,
+};
 use dhall_mock::mock::service::{
-    add_expectations_in_state, load_dhall_expectation, SharedState, State,
+    add_expectations_in_state, collect_expired_expectations, load_dhall_expectation,
+    load_dhall_expectation_file, SharedState, State,
 };
 use dhall_mock::start_servers;
 use dhall_mock::web::admin::AdminServerContext;
 use dhall_mock::web::mock::MockServerContext;
+use dhall_mock::web::tls::{ClientAuth, TlsContext};
 use futures::TryFutureExt;
 
 lazy_static! {
@@ -24,29 +31,79 @@ lazy_static! {
         (10001, 11001),
         (10002, 11002),
         (10003, 11003),
-        (10004, 11004)
+        (10004, 11004),
+        (10005, 11005),
+        (10006, 11006),
+        (10007, 11007),
+        (10008, 11008),
+        (10009, 11009),
+        (10010, 11010),
+        (10011, 11011),
+        (10012, 11012),
+        (10013, 11013),
+        (10014, 11014),
+        (10015, 11015),
+        (10016, 11016),
+        (10017, 11017),
+        (10018, 11018),
+        (10019, 11019),
+        (10020, 11020),
+        (10021, 11021),
+        (10022, 11022),
+        (10023, 11023),
+        (10024, 11024),
+        (10025, 11025),
+        (10026, 11026),
+        (10027, 11027),
+        (10028, 11028),
+        (10029, 11029),
+        (10030, 11030)
     ]));
 }
 
-async fn start_api() -> (SharedState, u16, u16) {
-    let (web_port, admin_port) = PORT_USED
+fn available_ports() -> (u16, u16) {
+    PORT_USED
         .clone()
         .lock()
         .expect("Can't get lock for availables ports")
         .deref_mut()
         .pop()
-        .expect("No available ports");
-    let state = Arc::new(RwLock::new(State {
-        expectations: vec![],
-    }));
+        .expect("No available ports")
+}
+
+async fn start_api() -> (SharedState, u16, u16) {
+    start_api_with_tls(None).await
+}
+
+async fn start_api_with_tls(tls: Option<TlsContext>) -> (SharedState, u16, u16) {
+    start_api_with(tls, None).await
+}
+
+async fn start_api_with(
+    tls: Option<TlsContext>,
+    proxy_to: Option<ProxyTarget>,
+) -> (SharedState, u16, u16) {
+    start_api_on(available_ports(), tls, proxy_to).await
+}
+
+async fn start_api_on(
+    (web_port, admin_port): (u16, u16),
+    tls: Option<TlsContext>,
+    proxy_to: Option<ProxyTarget>,
+) -> (SharedState, u16, u16) {
+    let state = Arc::new(RwLock::new(State::default()));
     tokio::spawn(start_servers(
         MockServerContext {
             http_bind: format!("0.0.0.0:{}", web_port),
             state: state.clone(),
+            diagnostics: true,
+            tls: tls.clone(),
+            proxy_to,
         },
         AdminServerContext {
             http_bind: format!("0.0.0.0:{}", admin_port),
             state: state.clone(),
+            tls_certificate: tls.map(|tls| tls.certificate),
         },
     ));
     (state, web_port, admin_port)
@@ -56,9 +113,8 @@ async fn start_api() -> (SharedState, u16, u16) {
 async fn test_api() {
     let (state, web_port, _) = start_api().await;
 
-    let conf = fs::read_to_string("./dhall/static.dhall").unwrap();
-    load_dhall_expectation("Init conf".to_string(), conf)
-        .and_then(|expectations| add_expectations_in_state(state.clone(), expectations))
+    load_dhall_expectation_file("./dhall/static.dhall".to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
         .await
         .expect("Error loading ./dhall/static.dhall conf");
 
@@ -72,9 +128,8 @@ async fn test_api() {
 async fn test_admin_api() {
     let (state, _, admin_port) = start_api().await;
 
-    let conf = fs::read_to_string("./dhall/static.dhall").unwrap();
-    load_dhall_expectation("Init conf".to_string(), conf)
-        .and_then(|expectations| add_expectations_in_state(state.clone(), expectations))
+    load_dhall_expectation_file("./dhall/static.dhall".to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
         .await
         .expect("Error loading ./dhall/static.dhall conf");
 
@@ -98,7 +153,7 @@ async fn test_admin_api_post_expectations() {
         let Mock = ./dhall/Mock/package.dhall
         let expectations = [
                                { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
-                                                              , path    = Some "/greet/toto"
+                                                              , path    = Some (Mock.Path.Exact "/greet/toto")
                                                               }
                                , response = Mock.HttpResponse::{ statusCode   = Some 201
                                                                , body         = Some "Hello, toto ! Ca vient du web"
@@ -117,22 +172,36 @@ async fn test_admin_api_post_expectations() {
     let state = state.read().unwrap();
 
     let expected = Expectation {
+        name: None,
+        tags: vec![],
+        scenario: None,
+        times: None,
+        active_after: None,
+        expires_after: None,
+        responses: None,
         request: HttpRequest {
             method: Some(HttpMethod::GET),
-            path: Some("/greet/toto".to_string()),
+            path: Some(RequestPath::Exact("/greet/toto".to_string())),
             body: None,
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         },
         response: HttpResponse {
             status_code: Some(201),
             status_reason: None,
             body: Some("Hello, toto ! Ca vient du web".to_string()),
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         },
     };
 
-    assert!(state.expectations.contains(&expected))
+    assert!(state.expectations.iter().any(|e| e.expectation == expected))
 }
 
 #[tokio::test]
@@ -149,7 +218,7 @@ async fn test_admin_fail_compile_configuration() {
         let Mock = ./dhall/Mock/package.dhall
         let expectations = [
                                { request  = { method  = Some Mock.HttpMethod.GET
-                                           , path    = Some "/greet/toto"
+                                           , path    = Some (Mock.Path.Exact "/greet/toto")
                                            }
                                , response = { statusCode   = Some +201
                                                , statusReason = None Text
@@ -169,20 +238,1200 @@ async fn test_admin_fail_compile_configuration() {
     let state = state.read().unwrap();
 
     let expected = Expectation {
+        name: None,
+        tags: vec![],
+        scenario: None,
+        times: None,
+        active_after: None,
+        expires_after: None,
+        responses: None,
         request: HttpRequest {
             method: Some(HttpMethod::GET),
-            path: Some("/greet/toto".to_string()),
+            path: Some(RequestPath::Exact("/greet/toto".to_string())),
             body: None,
             params: vec![],
-            headers: HashMap::new(),
+            headers: vec![],
+            matcher: None,
         },
         response: HttpResponse {
             status_code: Some(201),
             status_reason: None,
             body: Some("Hello, toto ! Ca vient du web".to_string()),
-            headers: HashMap::new(),
+            headers: vec![],
+            body_json: None,
+            body_template: None,
+            header_templates: vec![],
+            delay: None,
+            fault: None,
+            proxy: None,
         },
     };
 
-    assert!(!state.expectations.contains(&expected))
+    assert!(!state.expectations.iter().any(|e| e.expectation == expected))
+}
+
+#[tokio::test]
+async fn test_api_response_headers() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.POST
+                                            , path    = Some (Mock.Path.Exact "/users")
+                                            }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusCreated
+                                             , body       = Some "{ \"id\": 42 }"
+                                             , headers    = [ Mock.contentTypeJSON
+                                                            , { mapKey = "Location", mapValue = "/users/42" }
+                                                            ]
+                                                            # Mock.repeatedHeader "Set-Cookie" [ "session=42", "theme=dark" ]
+                                             }
+             }
+           ]
+        "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading response headers conf");
+
+    let api = format!("http://{}:{}/users", "localhost", web_port);
+    let resp = Client::new().post(&api).send().await.unwrap();
+
+    assert_eq!(reqwest::StatusCode::CREATED, resp.status());
+    assert_eq!("application/json", resp.headers()["Content-Type"]);
+    assert_eq!("/users/42", resp.headers()["Location"]);
+    let cookies: Vec<_> = resp
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|cookie| cookie.to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(vec!["session=42", "theme=dark"], cookies);
+    assert!(!resp.headers().contains_key("x-dhall-mock-status-reason"));
+}
+
+#[tokio::test]
+async fn test_api_response_status_reason() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
+                                            , path    = Some (Mock.Path.Exact "/teapot")
+                                            }
+             , response = Mock.HttpResponse::{ statusCode   = Some 418
+                                             , statusReason = Some "Short and stout"
+                                             , body         = Some "Here is my handle"
+                                             }
+             }
+           ]
+        "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading status reason conf");
+
+    let mut stream = TcpStream::connect(format!("localhost:{}", web_port))
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /teapot HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut raw_response = String::new();
+    stream.read_to_string(&mut raw_response).await.unwrap();
+
+    assert!(raw_response.starts_with("HTTP/1.1 418 Short and stout\r\n"));
+    assert!(!raw_response.contains("x-dhall-mock-status-reason"));
+    assert!(raw_response.ends_with("Here is my handle"));
+}
+
+#[tokio::test]
+async fn test_api_string_matchers() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ { request  = Mock.HttpRequest::{ method  = Some Mock.HttpMethod.GET
+                                            , path    = Some (Mock.Path.Matching (Mock.StringMatcher.Regex "/orders/[0-9]+"))
+                                            , params  = [ { key = "status", value = Mock.StringMatcher.StartsWith "PEND" } ]
+                                            , headers = [ { mapKey = "Authorization", mapValue = Mock.StringMatcher.Regex "Bearer .+" } ]
+                                            }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK }
+             }
+           ]
+        "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading string matchers conf");
+
+    let client = Client::new();
+    let api = format!("http://{}:{}/orders/42", "localhost", web_port);
+
+    let resp = client
+        .get(&api)
+        .query(&[("status", "PENDING")])
+        .header("Authorization", "Bearer token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let resp = client
+        .get(&api)
+        .query(&[("status", "PAID")])
+        .header("Authorization", "Bearer token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+
+    let resp = client
+        .get(&api)
+        .query(&[("status", "PENDING")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+}
+
+#[tokio::test]
+async fn test_admin_api_requests_journal() {
+    let (state, web_port, admin_port) = start_api().await;
+
+    load_dhall_expectation_file("./dhall/static.dhall".to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading ./dhall/static.dhall conf");
+
+    let client = Client::new();
+    client
+        .get(&format!("http://localhost:{}/greet/pwet", web_port))
+        .send()
+        .await
+        .unwrap();
+    client
+        .post(&format!("http://localhost:{}/unknown", web_port))
+        .body("who am I ?")
+        .send()
+        .await
+        .unwrap();
+
+    let api = format!("http://localhost:{}/requests", admin_port);
+    let requests = client
+        .get(&api)
+        .query(&[("matched", "false")])
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let requests: serde_json::Value = serde_json::from_str(&requests).unwrap();
+    let requests = requests.as_array().unwrap();
+    assert_eq!(1, requests.len());
+    assert_eq!("POST", requests[0]["request"]["method"]);
+    assert_eq!("/unknown", requests[0]["request"]["path"]);
+    assert_eq!("who am I ?", requests[0]["request"]["body"]);
+    assert_eq!(404, requests[0]["statusCode"]);
+    assert!(requests[0]["expectation"].is_null());
+
+    let requests = client
+        .get(&api)
+        .query(&[("method", "GET")])
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let requests: serde_json::Value = serde_json::from_str(&requests).unwrap();
+    assert_eq!(201, requests[0]["statusCode"]);
+    assert_eq!(
+        state.read().unwrap().expectations[0].id,
+        requests[0]["expectation"]["id"]
+    );
+    assert!(requests[0]["expectation"]["name"].is_null());
+    assert!(requests[0]["expectation"].get("request").is_none());
+
+    let resp = client
+        .get(&api)
+        .query(&[("from", "yesterday")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, resp.status());
+
+    let resp = client.delete(&api).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, resp.status());
+    assert!(state
+        .read()
+        .unwrap()
+        .journal()
+        .unwrap()
+        .find(&Default::default())
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_admin_api_verify() {
+    let (_, web_port, admin_port) = start_api().await;
+
+    let client = Client::new();
+    client
+        .post(&format!("http://localhost:{}/payments", web_port))
+        .body(r#"{ "amount": 100 }"#)
+        .send()
+        .await
+        .unwrap();
+
+    let api = format!("http://localhost:{}/verify", admin_port);
+    let resp = client
+        .post(&api)
+        .body(
+            r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in { request = Mock.HttpRequest::{ method = Some Mock.HttpMethod.POST
+                                         , path   = Some (Mock.Path.Exact "/payments")
+                                         , body   = Some (Mock.Body.JSON { json = "{ \"amount\": 100 }" })
+                                         }
+           , count   = Mock.CallCount.Exactly 1
+           }
+        "#,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let resp = client
+        .post(&api)
+        .header("Content-Type", "application/json")
+        .body(
+            r#"{ "request": { "method": "POST", "path": { "Exact": "/payments" }, "body": null, "params": [], "headers": [] },
+                 "count": "Never" }"#,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::EXPECTATION_FAILED, resp.status());
+    let report: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(serde_json::Value::Bool(false), report["passed"]);
+    assert_eq!(1, report["count"]);
+    assert_eq!("/payments", report["closest"][0]["request"]["path"]);
+
+    let resp = client
+        .post(&api)
+        .header("Content-Type", "application/json")
+        .body(r#"{ "count": "Never" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, resp.status());
+}
+
+#[tokio::test]
+async fn test_api_not_found_diagnostics() {
+    let (state, web_port, _) = start_api().await;
+
+    load_dhall_expectation_file("./dhall/static.dhall".to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading ./dhall/static.dhall conf");
+
+    let api = format!("http://{}:{}/greet/pwet", "localhost", web_port);
+    let resp = Client::new().delete(&api).send().await.unwrap();
+
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+    assert_eq!("application/json", resp.headers()["Content-Type"]);
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!("/greet/pwet", body["request"]["path"]);
+    assert_eq!(
+        "/greet/pwet",
+        body["closest"][0]["expectation"]["request"]["path"]["Exact"]
+    );
+    assert_eq!(
+        serde_json::json!(["Method"]),
+        body["closest"][0]["mismatches"]
+    );
+    assert_eq!(
+        serde_json::json!(["Method", "Path"]),
+        body["closest"][1]["mismatches"]
+    );
+}
+
+#[tokio::test]
+async fn test_admin_api_delete_and_replace_expectations() {
+    let (state, web_port, admin_port) = start_api().await;
+
+    load_dhall_expectation_file("./dhall/static.dhall".to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading ./dhall/static.dhall conf");
+
+    let client = Client::new();
+    let api = format!("http://localhost:{}/expectations", admin_port);
+    let greet = |name: &str| format!("http://localhost:{}/greet/{}", web_port, name);
+
+    let id = state.read().unwrap().expectations[0].id;
+    let resp = client
+        .delete(&format!("{}/{}", api, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, resp.status());
+    let resp = client
+        .delete(&format!("{}/{}", api, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+    let resp = client.get(&greet("pwet")).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+    let resp = client.get(&greet("wololo")).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let resp = client
+        .put(&api)
+        .body(
+            r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/greet/toto") }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK }
+             }
+           ]
+        "#,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+    let resp = client.get(&greet("wololo")).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+    let resp = client.get(&greet("toto")).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let resp = client.put(&api).body("[ 42 ]").send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, resp.status());
+    assert_eq!(1, state.read().unwrap().expectations.len());
+
+    let resp = client.delete(&api).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, resp.status());
+    assert!(state.read().unwrap().expectations.is_empty());
+}
+
+#[tokio::test]
+async fn test_admin_api_expectations_metadata() {
+    let (_, _, admin_port) = start_api().await;
+
+    let client = Client::new();
+    let api = format!("http://localhost:{}/expectations", admin_port);
+    let resp = client
+        .post(&api)
+        .body(
+            r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{ name     = Some "greet toto"
+                               , tags     = [ "greet", "web" ]
+                               , request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/greet/toto") }
+                               , response = Mock.HttpResponse::{=}
+                               }
+           , Mock.Expectation::{ request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/greet/titi") }
+                               , response = Mock.HttpResponse::{=}
+                               }
+           ]
+        "#,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::CREATED, resp.status());
+
+    let resp = client.get(&api).send().await.unwrap();
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+
+    assert_eq!("greet toto", body[0]["name"]);
+    assert_eq!(serde_json::json!(["greet", "web"]), body[0]["tags"]);
+    assert_eq!("POST web configuration", body[0]["source"]);
+    assert!(body[0]["loadedAt"].is_string());
+    assert!(body[1]["name"].is_null());
+    assert_eq!(serde_json::json!([]), body[1]["tags"]);
+    assert_ne!(body[0]["id"], body[1]["id"]);
+}
+
+#[tokio::test]
+async fn test_api_tls_self_signed() {
+    let tls = TlsContext::self_signed(&[String::from("localhost")]).unwrap();
+    let (state, web_port, admin_port) = start_api_with_tls(Some(tls)).await;
+
+    load_dhall_expectation_file("./dhall/static.dhall".to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading ./dhall/static.dhall conf");
+
+    let api = format!("http://localhost:{}/tls/certificate", admin_port);
+    let resp = reqwest::get(&api).await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+    let certificate = resp.text().await.unwrap();
+
+    let untrusted = Client::new()
+        .get(&format!("https://localhost:{}/greet/pwet", web_port))
+        .send()
+        .await;
+    assert!(untrusted.is_err());
+
+    let client = Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(certificate.as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let resp = client
+        .get(&format!("https://localhost:{}/greet/pwet", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::CREATED, resp.status());
+}
+
+/// CA bundle and a pkcs12 client identity signed by this CA for `common_name`.
+fn client_ca_and_identity(common_name: &str) -> (String, reqwest::Identity) {
+    let mut ca_params = rcgen::CertificateParams::new(vec![]);
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+
+    let mut client_params =
+        rcgen::CertificateParams::new(vec![String::from("partner.example.com")]);
+    client_params.distinguished_name = rcgen::DistinguishedName::new();
+    client_params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, common_name);
+    client_params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+    let client = rcgen::Certificate::from_params(client_params).unwrap();
+
+    let certificate =
+        openssl::x509::X509::from_pem(client.serialize_pem_with_signer(&ca).unwrap().as_bytes())
+            .unwrap();
+    let key =
+        openssl::pkey::PKey::private_key_from_pem(client.serialize_private_key_pem().as_bytes())
+            .unwrap();
+    let pkcs12 = openssl::pkcs12::Pkcs12::builder()
+        .build("", common_name, &key, &certificate)
+        .unwrap();
+    (
+        ca.serialize_pem().unwrap(),
+        reqwest::Identity::from_pkcs12_der(&pkcs12.to_der().unwrap(), "").unwrap(),
+    )
+}
+
+async fn start_mutual_tls_api(
+    ca_bundle: &str,
+    client_auth: ClientAuth,
+) -> (SharedState, u16, reqwest::Certificate) {
+    let tls = TlsContext::self_signed(&[String::from("localhost")])
+        .unwrap()
+        .with_client_ca(ca_bundle, client_auth)
+        .unwrap();
+    let server_certificate = reqwest::Certificate::from_pem(tls.certificate.as_bytes()).unwrap();
+    let (state, web_port, _) = start_api_with_tls(Some(tls)).await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        let partner = Mock.criterion (Mock.Criterion.ClientSubject (Mock.StringMatcher.Exact "CN=partner-a"))
+        in [ { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/orders"), matcher = Mock.buildMatcher partner }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK }
+             }
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/orders"), matcher = Mock.buildMatcher (Mock.not partner) }
+             , response = Mock.HttpResponse::{ statusCode = Some 403 }
+             }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading mutual tls conf");
+    (state, web_port, server_certificate)
+}
+
+#[tokio::test]
+async fn test_api_mutual_tls_optional_client_certificate() {
+    let (ca_bundle, partner_a) = client_ca_and_identity("partner-a");
+    let (_, partner_b) = client_ca_and_identity("partner-b");
+    let (_, web_port, server_certificate) =
+        start_mutual_tls_api(&ca_bundle, ClientAuth::Optional).await;
+    let api = format!("https://localhost:{}/orders", web_port);
+    let client = |identity: Option<reqwest::Identity>| {
+        let builder = Client::builder().add_root_certificate(server_certificate.clone());
+        match identity {
+            Some(identity) => builder.identity(identity),
+            None => builder,
+        }
+        .build()
+        .unwrap()
+    };
+
+    let resp = client(Some(partner_a)).get(&api).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let resp = client(None).get(&api).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::FORBIDDEN, resp.status());
+
+    // signed by another CA
+    assert!(client(Some(partner_b)).get(&api).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_api_mutual_tls_required_client_certificate() {
+    let (ca_bundle, partner_a) = client_ca_and_identity("partner-a");
+    let (state, web_port, server_certificate) =
+        start_mutual_tls_api(&ca_bundle, ClientAuth::Required).await;
+    let api = format!("https://localhost:{}/orders", web_port);
+
+    let anonymous = Client::builder()
+        .add_root_certificate(server_certificate.clone())
+        .build()
+        .unwrap();
+    assert!(anonymous.get(&api).send().await.is_err());
+
+    let client = Client::builder()
+        .add_root_certificate(server_certificate)
+        .identity(partner_a)
+        .build()
+        .unwrap();
+    let resp = client.get(&api).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let state = state.read().unwrap();
+    let certificate = state
+        .journal()
+        .unwrap()
+        .entries()
+        .last()
+        .unwrap()
+        .request
+        .client_certificate
+        .clone()
+        .unwrap();
+    assert_eq!("CN=partner-a", certificate.subject);
+    assert_eq!(vec!["partner.example.com"], certificate.subject_alt_names);
+}
+
+#[tokio::test]
+async fn test_api_response_templates() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Template "/users/{id}") }
+             , response = Mock.HttpResponse::{ statusCode      = Mock.statusOK
+                                             , bodyTemplate    = Some "{{method}} user {{path.id}} named {{body.name}} on page {{query.page}}"
+                                             , headerTemplates = [ { mapKey = "X-Request-Id", mapValue = "{{header.X-Request-Id}}" } ]
+                                             }
+             }
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/echo") }
+             , response = Mock.HttpResponse::{ statusCode      = Mock.statusOK
+                                             , headerTemplates = [ { mapKey = "X-Echo", mapValue = "{{body}}" } ]
+                                             }
+             }
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/broken") }
+             , response = Mock.HttpResponse::{ statusCode      = Mock.statusOK
+                                             , headerTemplates = [ { mapKey = "X Broken", mapValue = "{{method}}" } ]
+                                             }
+             }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading templates conf");
+
+    let resp = Client::new()
+        .put(&format!("http://localhost:{}/users/42?page=3", web_port))
+        .header("X-Request-Id", "abc-123")
+        .body(r#"{ "name": "robert" }"#)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+    assert_eq!("abc-123", resp.headers()["X-Request-Id"]);
+    assert_eq!(
+        "PUT user 42 named robert on page 3",
+        resp.text().await.unwrap()
+    );
+
+    let resp = Client::new()
+        .post(&format!("http://localhost:{}/echo", web_port))
+        .body("first line\r\nX-Injected: true\n")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+    assert_eq!("first lineX-Injected: true", resp.headers()["X-Echo"]);
+    assert!(!resp.headers().contains_key("X-Injected"));
+
+    let resp = Client::new()
+        .get(&format!("http://localhost:{}/broken", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+    let state = state.read().unwrap();
+    let journal = state.journal().unwrap();
+    let recorded = journal.entries().last().unwrap();
+    assert_eq!("/broken", recorded.request.path);
+    assert_eq!(500, recorded.status_code);
+}
+
+#[tokio::test]
+async fn test_api_json_value_bodies() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        let user : Mock.JSON
+            = \(JSON : Type)
+           -> \(json : { array : List JSON -> JSON, bool : Bool -> JSON, double : Double -> JSON, integer : Integer -> JSON, null : JSON, object : List { mapKey : Text, mapValue : JSON } -> JSON, string : Text -> JSON })
+           -> json.object [ { mapKey = "id", mapValue = json.integer +42 }, { mapKey = "roles", mapValue = json.array [ json.string "admin" ] } ]
+        in [ { request  = Mock.HttpRequest::{ body = Some (Mock.jsonSubsetBody Mock.ArrayMatching.Contains user) }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusCreated, bodyJSON = Mock.jsonResponseBody user }
+             }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading json value conf");
+
+    let resp = Client::new()
+        .post(&format!("http://localhost:{}/users", web_port))
+        .body(r#"{ "name": "robert", "id": 42, "roles": ["user", "admin"] }"#)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(reqwest::StatusCode::CREATED, resp.status());
+    assert_eq!(r#"{"id":42,"roles":["admin"]}"#, resp.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_api_delayed_response_does_not_block_other_requests() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/slow") }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, delay = Some (Mock.Delay.Fixed 500) }
+             }
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/fast") }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK }
+             }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading delay conf");
+
+    let start = Instant::now();
+    let slow = tokio::spawn(
+        Client::new()
+            .get(&format!("http://localhost:{}/slow", web_port))
+            .send(),
+    );
+    let fast = Client::new()
+        .get(&format!("http://localhost:{}/fast", web_port))
+        .send()
+        .await
+        .unwrap();
+    let fast_elapsed = start.elapsed();
+    let slow = slow.await.unwrap().unwrap();
+
+    assert_eq!(reqwest::StatusCode::OK, fast.status());
+    assert_eq!(reqwest::StatusCode::OK, slow.status());
+    assert!(fast_elapsed < Duration::from_millis(500));
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn test_api_faults() {
+    let (state, web_port, _) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        let faulty = \(path : Text) -> \(fault : Mock.Fault) ->
+              { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact path) }
+              , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "0123456789", fault = Some fault }
+              }
+        in [ faulty "/reset" Mock.Fault.ConnectionReset
+           , faulty "/truncated" Mock.Fault.TruncatedBody
+           , faulty "/malformed" Mock.Fault.MalformedResponse
+           , faulty "/empty" Mock.Fault.EmptyResponse
+           , faulty "/stall" Mock.Fault.Stall
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/fine") }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "0123456789" }
+             }
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/echo") }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, bodyTemplate = Some "{{body}}" }
+             }
+           , { request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/reason") }
+             , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, statusReason = Some "Fine", body = Some "0123456789" }
+             }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading faults conf");
+    let get = |path: &str| {
+        Client::builder()
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap()
+            .get(&format!("http://localhost:{}{}", web_port, path))
+            .send()
+    };
+
+    for path in &["/reset", "/malformed", "/empty"] {
+        let error = get(path).await.expect_err(path);
+        assert!(!error.is_timeout(), "{} : {}", path, error);
+    }
+
+    let truncated = get("/truncated").await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, truncated.status());
+    assert!(truncated.text().await.is_err());
+
+    assert!(get("/stall").await.unwrap_err().is_timeout());
+
+    assert_eq!(
+        "0123456789",
+        get("/fine").await.unwrap().text().await.unwrap()
+    );
+
+    // internal headers are only read in response heads, on the same connection
+    let client = Client::new();
+    let body = "\r\nx-dhall-mock-fault: connection-reset\r\nx-dhall-mock-status-reason: Nope\r\n";
+    let echo = client
+        .post(&format!("http://localhost:{}/echo", web_port))
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(body, echo.text().await.unwrap());
+    let head = client
+        .head(&format!("http://localhost:{}/reason", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!("10", head.headers()["content-length"]);
+    assert!(!head.headers().contains_key("x-dhall-mock-head-response"));
+    let reason = client
+        .get(&format!("http://localhost:{}/reason", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!("0123456789", reason.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_api_scenario_states() {
+    let (state, web_port, admin_port) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        let order = \(status : Text) -> \(scenario : Mock.Scenario.Type) ->
+              Mock.Expectation::{ scenario = Some scenario
+                                , request  = Mock.HttpRequest::{ method = Some Mock.HttpMethod.GET, path = Some (Mock.Path.Exact "/order") }
+                                , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some status }
+                                }
+        in [ order "PENDING" Mock.Scenario::{ name = "order flow", requiredState = Some Mock.scenarioStarted }
+           , order "PAID" Mock.Scenario::{ name = "order flow", requiredState = Some "PAID" }
+           , Mock.Expectation::{ scenario = Some Mock.Scenario::{ name = "order flow", newState = Some "PAID" }
+                               , request  = Mock.HttpRequest::{ method = Some Mock.HttpMethod.POST, path = Some (Mock.Path.Exact "/order/pay") }
+                               , response = Mock.HttpResponse::{ statusCode = Mock.statusOK }
+                               }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading scenario conf");
+    let client = Client::new();
+    let order = format!("http://localhost:{}/order", web_port);
+    let scenarios = format!("http://localhost:{}/scenarios", admin_port);
+
+    let resp = client.get(&order).send().await.unwrap();
+    assert_eq!("PENDING", resp.text().await.unwrap());
+
+    let resp = client.post(&format!("{}/pay", order)).send().await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, resp.status());
+
+    let resp = client.get(&order).send().await.unwrap();
+    assert_eq!("PAID", resp.text().await.unwrap());
+
+    let resp = client.get(&scenarios).send().await.unwrap();
+    assert_eq!(r#"{"order flow":"PAID"}"#, resp.text().await.unwrap());
+
+    let resp = client
+        .delete(&format!("{}/order%20flow", scenarios))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NO_CONTENT, resp.status());
+
+    let resp = client.get(&order).send().await.unwrap();
+    assert_eq!("PENDING", resp.text().await.unwrap());
+
+    let resp = client
+        .delete(&format!("{}/payment", scenarios))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, resp.status());
+}
+
+#[tokio::test]
+async fn test_api_limited_times_expectation() {
+    let (state, web_port, admin_port) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{ times    = Some 3
+                               , request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/token") }
+                               , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "limited" }
+                               }
+           , Mock.Expectation::{ request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/token") }
+                               , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "fallback" }
+                               }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading times conf");
+    let client = Client::new();
+    let url = format!("http://localhost:{}/token", web_port);
+
+    let bodies = futures::future::join_all(
+        (0..10).map(|_| client.get(&url).send().and_then(|resp| resp.text())),
+    )
+    .await;
+
+    let limited = bodies
+        .iter()
+        .filter(|body| body.as_ref().unwrap() == "limited")
+        .count();
+    assert_eq!(3, limited);
+
+    let resp = client
+        .get(&format!("http://localhost:{}/expectations", admin_port))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(3, body[0]["times"]);
+    assert_eq!(0, body[0]["remaining"]);
+    assert!(body[1]["remaining"].is_null());
+}
+
+#[tokio::test]
+async fn test_api_expectations_activation_window() {
+    let (state, web_port, admin_port) = start_api().await;
+    tokio::spawn(collect_expired_expectations(
+        state.clone(),
+        Duration::from_millis(50),
+    ));
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{ expiresAfter = Some 300
+                               , request      = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/status") }
+                               , response     = Mock.HttpResponse::{ statusCode = Some 503, body = Some "down" }
+                               }
+           , Mock.Expectation::{ activeAfter  = Some 300
+                               , request      = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/status") }
+                               , response     = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "up" }
+                               }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading activation window conf");
+    let client = Client::new();
+    let url = format!("http://localhost:{}/status", web_port);
+
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!("down", resp.text().await.unwrap());
+
+    tokio::time::delay_for(Duration::from_millis(400)).await;
+
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!("up", resp.text().await.unwrap());
+
+    let resp = client
+        .get(&format!("http://localhost:{}/expectations", admin_port))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(1, body.as_array().unwrap().len());
+    assert_eq!(300, body[0]["activeAfter"]);
+}
+
+#[tokio::test]
+async fn test_api_response_sequence() {
+    let (state, web_port, admin_port) = start_api().await;
+
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{ request   = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/orders/1") }
+                               , responses = Some (Mock.ResponseSequence.Sequential
+                                   [ Mock.HttpResponse::{ statusCode = Mock.statusNotFound }
+                                   , Mock.HttpResponse::{ statusCode = Mock.statusNotFound, delay = Some (Mock.Delay.Fixed 10) }
+                                   , Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "found" }
+                                   ])
+                               }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading response sequence conf");
+    let client = Client::new();
+    let url = format!("http://localhost:{}/orders/1", web_port);
+
+    let mut statuses = vec![];
+    for _ in 0..4 {
+        statuses.push(client.get(&url).send().await.unwrap().status().as_u16());
+    }
+    assert_eq!(vec![404, 404, 200, 200], statuses);
+
+    let resp = client
+        .get(&format!("http://localhost:{}/expectations", admin_port))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(4, body[0]["served"]);
+}
+
+#[tokio::test]
+async fn test_api_proxy_requests() {
+    let (upstream_state, upstream_port, _) = start_api().await;
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{ request  = Mock.HttpRequest::{ path    = Some (Mock.Path.Exact "/api/users")
+                                                               , headers = [ Mock.matchHeader { mapKey = "Authorization", mapValue = "Bearer upstream" } ]
+                                                               }
+                               , response = Mock.HttpResponse::{ statusCode = Mock.statusOK
+                                                               , body       = Some "upstream users"
+                                                               , headers    = [ { mapKey = "X-Upstream", mapValue = "users" } ]
+                                                               }
+                               }
+           , Mock.Expectation::{ request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact "/health") }
+                               , response = Mock.HttpResponse::{ statusCode = Mock.statusOK, body = Some "upstream health" }
+                               }
+           ]
+    "#;
+    load_dhall_expectation("Upstream conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(upstream_state.clone(), configuration))
+        .await
+        .expect("Error loading upstream conf");
+
+    let fallback = format!("http://localhost:{}", upstream_port)
+        .parse::<ProxyTarget>()
+        .unwrap();
+    let (state, web_port, _) = start_api_with(None, Some(fallback)).await;
+    let conf = format!(
+        r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{{ request  = Mock.HttpRequest::{{ path = Some (Mock.Path.Exact "/users") }}
+                                , response = Mock.HttpResponse::{{ proxy   = Some Mock.Proxy::{{ target  = "http://localhost:{}/api"
+                                                                                             , headers = [ {{ mapKey = "Authorization", mapValue = "Bearer upstream" }} ]
+                                                                                             }}
+                                                                 , headers = [ {{ mapKey = "X-Upstream", mapValue = "mock" }} ]
+                                                                 }}
+                                }}
+           ]
+    "#,
+        upstream_port
+    );
+    load_dhall_expectation("Proxy conf".to_string(), conf)
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading proxy conf");
+    let client = Client::new();
+
+    let resp = client
+        .get(&format!("http://localhost:{}/users", web_port))
+        .header("Authorization", "Bearer client")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, resp.status().as_u16());
+    assert_eq!("mock", resp.headers()["x-upstream"]);
+    assert_eq!("upstream users", resp.text().await.unwrap());
+
+    let resp = client
+        .get(&format!("http://localhost:{}/health", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, resp.status().as_u16());
+    assert_eq!("upstream health", resp.text().await.unwrap());
+
+    let resp = client
+        .get(&format!("http://localhost:{}/unknown", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(404, resp.status().as_u16());
+}
+
+#[tokio::test]
+async fn test_api_proxy_fallback_forwards_any_request() {
+    // Upstream echoing the request method and raw body
+    let (upstream_port, _) = available_ports();
+    let upstream = hyper::Server::bind(&([127, 0, 0, 1], upstream_port).into()).serve(
+        hyper::service::make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(hyper::service::service_fn(
+                |req: hyper::Request<hyper::Body>| async move {
+                    let method = req.method().to_string();
+                    let body = hyper::body::to_bytes(req.into_body()).await?;
+                    Ok::<_, hyper::Error>(
+                        hyper::Response::builder()
+                            .header("X-Upstream-Method", method)
+                            .body(hyper::Body::from(body))
+                            .unwrap(),
+                    )
+                },
+            ))
+        }),
+    );
+    tokio::spawn(upstream);
+
+    let fallback = format!("http://localhost:{}", upstream_port)
+        .parse::<ProxyTarget>()
+        .unwrap();
+    let (state, web_port, _) = start_api_with(None, Some(fallback)).await;
+    let conf = r#"
+        let Mock = ./dhall/Mock/package.dhall
+        in [ Mock.Expectation::{ request  = Mock.HttpRequest::{ method = Some Mock.HttpMethod.PATCH, path = Some (Mock.Path.Exact "/local") }
+                               , response = Mock.HttpResponse::{ body = Some "local patch" }
+                               }
+           ]
+    "#;
+    load_dhall_expectation("Init conf".to_string(), conf.to_string())
+        .and_then(|configuration| add_expectations_in_state(state.clone(), configuration))
+        .await
+        .expect("Error loading conf");
+    let client = Client::new();
+
+    let resp = client
+        .patch(&format!("http://localhost:{}/local", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!("local patch", resp.text().await.unwrap());
+
+    let resp = client
+        .patch(&format!("http://localhost:{}/users/1", web_port))
+        .body("{}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, resp.status().as_u16());
+    assert_eq!("PATCH", resp.headers()["X-Upstream-Method"]);
+    assert_eq!("{}", resp.text().await.unwrap());
+
+    let resp = client
+        .request(
+            reqwest::Method::OPTIONS,
+            &format!("http://localhost:{}/users", web_port),
+        )
+        .header("Origin", "http://front.local")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!("OPTIONS", resp.headers()["X-Upstream-Method"]);
+
+    let binary: Vec<u8> = vec![0xff, 0xfe, 0x00, 0x80];
+    let resp = client
+        .post(&format!("http://localhost:{}/upload", web_port))
+        .body(binary.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!("POST", resp.headers()["X-Upstream-Method"]);
+    assert_eq!(binary, resp.bytes().await.unwrap().to_vec());
+}
+
+#[tokio::test]
+async fn test_api_proxy_loop() {
+    let ports = available_ports();
+    let fallback = format!("http://localhost:{}", ports.0)
+        .parse::<ProxyTarget>()
+        .unwrap();
+    let (_, web_port, _) = start_api_on(ports, None, Some(fallback)).await;
+
+    let resp = Client::new()
+        .get(&format!("http://localhost:{}/health", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(508, resp.status().as_u16());
+}
+
+#[tokio::test]
+async fn test_api_proxy_unreachable_upstream() {
+    let fallback = "http://localhost:1".parse::<ProxyTarget>().unwrap();
+    let (_, web_port, _) = start_api_with(None, Some(fallback)).await;
+
+    let resp = Client::new()
+        .get(&format!("http://localhost:{}/health", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(502, resp.status().as_u16());
+}
+
+#[tokio::test]
+async fn test_load_configuration_directory_with_relative_imports() {
+    let (state, web_port, _) = start_api().await;
+    let dir = std::env::temp_dir().join(format!("dhall-mock-imports-{}", std::process::id()));
+    let billing = dir.join("mocks").join("billing");
+    fs::create_dir_all(dir.join("Mock")).unwrap();
+    fs::create_dir_all(&billing).unwrap();
+    fs::copy("./dhall/Mock/package.dhall", dir.join("Mock/package.dhall")).unwrap();
+    fs::write(
+        billing.join("common.dhall"),
+        r#"{ invoices = "/billing/invoices" }"#,
+    )
+    .unwrap();
+    fs::write(
+        billing.join("invoices.dhall"),
+        r###"
+            let Mock = ../../Mock/package.dhall
+            let common = ./common.dhall
+            in [ Mock.Expectation::{ request  = Mock.HttpRequest::{ path = Some (Mock.Path.Exact common.invoices) }
+                                   , response = Mock.HttpResponse::{ body = Some "invoices" }
+                                   }
+               ]
+        "###,
+    )
+    .unwrap();
+
+    let files = configuration_files(&[dir.to_string_lossy().to_string()]).unwrap();
+    assert_eq!(2, files.len());
+    load_configuration_files(state.clone(), files).await;
+
+    let resp = Client::new()
+        .get(&format!("http://localhost:{}/billing/invoices", web_port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, resp.status().as_u16());
+    assert_eq!("invoices", resp.text().await.unwrap());
+
+    fs::remove_dir_all(&dir).unwrap();
 }